@@ -0,0 +1,82 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Grammar ABI versions tree-sitter 0.20 can load (`MIN_COMPATIBLE_LANGUAGE_VERSION`
+/// and `LANGUAGE_VERSION` of the runtime).
+const SUPPORTED_ABI: std::ops::RangeInclusive<u32> = 13..=14;
+
+/// Compiles the tree-sitter-haproxy grammar checkout in `HAPROXY_GRAMMAR_DIR` into
+/// the LSP server.
+///
+/// `build.sh` fetches the commit pinned in `extension.toml` into `grammars/haproxy`
+/// and points the variable there, so the LSP parses with exactly the grammar the
+/// editor highlights with. This script only reads the checkout; the compiled
+/// library goes to `OUT_DIR`.
+fn main() {
+    println!("cargo:rustc-check-cfg=cfg(haproxy_grammar)");
+    println!("cargo:rerun-if-env-changed=HAPROXY_GRAMMAR_DIR");
+    // Without this, a build without the grammar would rerun on every change to the package
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // The Zed extension itself is WebAssembly and never needs the grammar.
+    if env::var_os("CARGO_FEATURE_LSP_SERVER").is_none() {
+        return;
+    }
+
+    let grammar_dir = match env::var_os("HAPROXY_GRAMMAR_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            println!(
+                "cargo:warning=HAPROXY_GRAMMAR_DIR is not set, haproxy-lsp will use line-based parsing \
+                 (run ./build.sh to fetch the grammar)"
+            );
+            return;
+        }
+    };
+    let src_dir = grammar_dir.join("src");
+    let parser = src_dir.join("parser.c");
+    if !parser.exists() {
+        println!(
+            "cargo:warning=tree-sitter-haproxy grammar not found in {}, haproxy-lsp will use line-based parsing",
+            grammar_dir.display()
+        );
+        return;
+    }
+    println!("cargo:rerun-if-changed={}", parser.display());
+
+    // A grammar generated for another runtime compiles fine and only fails in
+    // `Parser::set_language`, so refuse it here instead
+    let source = fs::read_to_string(&parser).unwrap_or_else(|err| panic!("{}: {}", parser.display(), err));
+    match language_version(&source) {
+        Some(abi) if SUPPORTED_ABI.contains(&abi) => {}
+        abi => panic!(
+            "{} was generated for tree-sitter ABI {}, but the tree-sitter 0.20 runtime loads ABI {} to {}; \
+             check out the grammar commit pinned in extension.toml",
+            parser.display(),
+            abi.map_or("(unknown)".to_string(), |abi| abi.to_string()),
+            SUPPORTED_ABI.start(),
+            SUPPORTED_ABI.end()
+        ),
+    }
+
+    let mut build = cc::Build::new();
+    build.include(&src_dir).file(&parser).warnings(false);
+
+    let scanner = src_dir.join("scanner.c");
+    if scanner.exists() {
+        println!("cargo:rerun-if-changed={}", scanner.display());
+        build.file(&scanner);
+    }
+
+    build.compile("tree-sitter-haproxy");
+    println!("cargo:rustc-cfg=haproxy_grammar");
+}
+
+/// The `LANGUAGE_VERSION` a generated `parser.c` declares.
+fn language_version(source: &str) -> Option<u32> {
+    source
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("#define LANGUAGE_VERSION"))
+        .and_then(|value| value.trim().parse().ok())
+}
@@ -3,6 +3,18 @@ set -e
 
 echo "Building HAProxy Zed Extension with LSP server..."
 
+# Fetch the tree-sitter grammar pinned in extension.toml so the LSP parses
+# with the same grammar Zed highlights with
+GRAMMAR_REPO=$(sed -n 's/^repository = "\(.*tree-sitter-haproxy\)"/\1/p' extension.toml)
+GRAMMAR_COMMIT=$(sed -n 's/^commit = "\(.*\)"/\1/p' extension.toml)
+if [ ! -f grammars/haproxy/src/parser.c ]; then
+    echo "Fetching tree-sitter-haproxy grammar..."
+    rm -rf grammars/haproxy
+    git clone --quiet "$GRAMMAR_REPO" grammars/haproxy
+    git -C grammars/haproxy checkout --quiet "$GRAMMAR_COMMIT"
+fi
+export HAPROXY_GRAMMAR_DIR="$PWD/grammars/haproxy"
+
 # Build the LSP server binary
 echo "Building LSP server..."
 cargo build --bin haproxy-lsp --features lsp-server --release
@@ -0,0 +1,433 @@
+//! Listening and server addresses as HAProxy reads them: an optional `family@`
+//! prefix, then a host and port, a socket path or a file descriptor.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::config::Directive;
+use crate::keywords::{Version, OLDEST};
+use crate::tokenizer::Token;
+
+/// Whether an address is listened on or connected to. Only a server may leave the
+/// port out or give it relative to the client's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressRole {
+    Bind,
+    Server,
+    /// A `peer` line: connected to by the other peers and listened on by the local
+    /// one, so it takes exactly one port.
+    Peer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFamily {
+    Ipv4,
+    Ipv6,
+    /// A name resolved when HAProxy starts, or at runtime through `resolvers`.
+    Hostname,
+    Unix,
+    AbstractUnix,
+    FileDescriptor,
+    SocketPair,
+    /// `rhttp@`: reverse HTTP. A `bind` opens the connections through the server
+    /// named after it; a server with no name reuses the ones its clients attached.
+    ReverseHttp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ports {
+    Single(u16),
+    /// A `bind` range such as `8000-8010`.
+    Range(u16, u16),
+    /// A server port relative to the one the client connected to, e.g. `+1000`.
+    Offset(i32),
+}
+
+/// What one address means, for validation and hover.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    /// The `family@` prefix as written, e.g. `quic4@`.
+    pub prefix: Option<String>,
+    pub family: AddressFamily,
+    /// Host, socket path or descriptor number; empty or `*` for any IPv4 address.
+    pub host: String,
+    pub ports: Option<Ports>,
+}
+
+/// An address prefix of `bind` and `server` lines, e.g. `quic4@`.
+#[derive(Debug)]
+pub struct AddressPrefix {
+    pub name: &'static str,
+    /// The family it forces, if any.
+    pub family: Option<AddressFamily>,
+    /// The transport it selects, e.g. for hover.
+    pub transport: &'static str,
+    /// The first release accepting it.
+    pub since: Version,
+}
+
+const fn prefix(name: &'static str, family: Option<AddressFamily>, transport: &'static str) -> AddressPrefix {
+    AddressPrefix { name, family, transport, since: OLDEST }
+}
+
+impl AddressPrefix {
+    const fn since(mut self, major: u8, minor: u8) -> Self {
+        self.since = Version::new(major, minor);
+        self
+    }
+}
+
+/// Every address prefix HAProxy knows.
+pub static ADDRESS_PREFIXES: &[AddressPrefix] = &[
+    prefix("ipv4@", Some(AddressFamily::Ipv4), "TCP"),
+    prefix("ipv6@", Some(AddressFamily::Ipv6), "TCP"),
+    prefix("tcp@", None, "TCP"),
+    prefix("tcp4@", Some(AddressFamily::Ipv4), "TCP"),
+    prefix("tcp6@", Some(AddressFamily::Ipv6), "TCP"),
+    prefix("udp@", None, "UDP").since(2, 3),
+    prefix("udp4@", Some(AddressFamily::Ipv4), "UDP").since(2, 3),
+    prefix("udp6@", Some(AddressFamily::Ipv6), "UDP").since(2, 3),
+    prefix("quic4@", Some(AddressFamily::Ipv4), "QUIC (HTTP/3 over UDP)").since(2, 6),
+    prefix("quic6@", Some(AddressFamily::Ipv6), "QUIC (HTTP/3 over UDP)").since(2, 6),
+    prefix("mptcp@", None, "Multipath TCP").since(3, 1),
+    prefix("mptcp4@", Some(AddressFamily::Ipv4), "Multipath TCP").since(3, 1),
+    prefix("mptcp6@", Some(AddressFamily::Ipv6), "Multipath TCP").since(3, 1),
+    prefix("unix@", Some(AddressFamily::Unix), "stream"),
+    prefix("uxst@", Some(AddressFamily::Unix), "stream"),
+    prefix("uxdg@", Some(AddressFamily::Unix), "datagram"),
+    prefix("abns@", Some(AddressFamily::AbstractUnix), "stream"),
+    prefix("abnsz@", Some(AddressFamily::AbstractUnix), "stream").since(3, 1),
+    prefix("fd@", Some(AddressFamily::FileDescriptor), "stream"),
+    prefix("sockpair@", Some(AddressFamily::SocketPair), "stream").since(1, 9),
+    prefix("rhttp@", Some(AddressFamily::ReverseHttp), "HTTP").since(2, 9),
+];
+
+/// Looks up the prefix `text` starts with.
+pub fn address_prefix(text: &str) -> Option<&'static AddressPrefix> {
+    let (name, _) = text.split_once('@')?;
+    ADDRESS_PREFIXES.iter().find(|prefix| prefix.name.len() == name.len() + 1 && prefix.name.starts_with(name))
+}
+
+/// Parses one address, without the commas that separate several on a `bind` line.
+pub fn parse_address(text: &str, role: AddressRole) -> Result<Address, String> {
+    let (prefix, rest, forced) = match text.split_once('@') {
+        Some((name, rest)) if !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric()) => {
+            match address_prefix(text) {
+                Some(known) => (Some(known.name.to_string()), rest, known.family),
+                None => return Err(format!("unknown address family `{}@`", name)),
+            }
+        }
+        _ => (None, text, None),
+    };
+    let address = |family, host: &str, ports| Address { prefix: prefix.clone(), family, host: host.to_string(), ports };
+
+    match forced {
+        // A server reuses the reverse connections its clients attached
+        Some(AddressFamily::ReverseHttp) if rest.is_empty() && role == AddressRole::Server => {
+            return Ok(address(AddressFamily::ReverseHttp, rest, None));
+        }
+        Some(family @ (AddressFamily::Unix | AddressFamily::AbstractUnix | AddressFamily::ReverseHttp)) => {
+            if rest.is_empty() {
+                return Err(format!("`{}` needs a name after it", text));
+            }
+            return Ok(address(family, rest, None));
+        }
+        Some(family @ (AddressFamily::FileDescriptor | AddressFamily::SocketPair)) => {
+            if rest.parse::<u32>().is_err() {
+                let prefix = prefix.as_deref().unwrap_or_default();
+                return Err(format!("`{}` needs a file descriptor number, got `{}`", prefix, rest));
+            }
+            return Ok(address(family, rest, None));
+        }
+        _ => {}
+    }
+    if prefix.is_none() && rest.starts_with('/') {
+        return Ok(address(AddressFamily::Unix, rest, None));
+    }
+
+    let (host, port) = split_host_port(rest)?;
+    let family = host_family(host)?;
+    match (forced, family) {
+        (Some(AddressFamily::Ipv4), AddressFamily::Ipv6) | (Some(AddressFamily::Ipv6), AddressFamily::Ipv4)
+            if !host.is_empty() && host != "*" =>
+        {
+            let prefix = prefix.as_deref().unwrap_or_default();
+            return Err(format!("`{}` is not an address of the `{}` family", host, prefix));
+        }
+        _ => {}
+    }
+    // `:80` and `*:80` listen on any address of the family the prefix asks for
+    let family = match (forced, host) {
+        (Some(AddressFamily::Ipv6), "" | "*") => AddressFamily::Ipv6,
+        _ => family,
+    };
+
+    let ports = match port {
+        Some(port) => Some(parse_ports(port, role)?),
+        None if role == AddressRole::Bind => {
+            return Err(format!("`{}` has no port, e.g. `:80` or `*:443`", text));
+        }
+        None if role == AddressRole::Peer => {
+            return Err(format!("peer address `{}` has no port, e.g. `{}:10000`", text, text));
+        }
+        None => None,
+    };
+    if role == AddressRole::Server && matches!(host, "" | "*") {
+        return Err(format!("`{}` has no host to connect to", text));
+    }
+    Ok(address(family, host, ports))
+}
+
+/// Splits `host:port`, `[v6]:port` and IPv6 with the port after the last colon,
+/// like `:::80`. A trailing colon (`::1:`) means no port.
+fn split_host_port(text: &str) -> Result<(&str, Option<&str>), String> {
+    if let Some(bracketed) = text.strip_prefix('[') {
+        let (host, rest) = bracketed.split_once(']').ok_or_else(|| format!("`{}` lacks a closing `]`", text))?;
+        return match rest {
+            "" => Ok((host, None)),
+            _ => match rest.strip_prefix(':') {
+                Some(port) => Ok((host, Some(port))),
+                None => Err(format!("unexpected `{}` after `[{}]`", rest, host)),
+            },
+        };
+    }
+
+    match text.rsplit_once(':') {
+        // `2001:db8::1` would read as host `2001:db8:` and port 1
+        Some((host, _)) if host.parse::<Ipv6Addr>().is_err() && text.parse::<Ipv6Addr>().is_ok() => {
+            Err(format!("the port of `{}` goes after one more colon, e.g. `{}:80`", text, text))
+        }
+        Some((host, "")) => Ok((host, None)),
+        Some((host, port)) => Ok((host, Some(port))),
+        None => Ok((text, None)),
+    }
+}
+
+fn host_family(host: &str) -> Result<AddressFamily, String> {
+    if host.is_empty() || host == "*" {
+        Ok(AddressFamily::Ipv4)
+    } else if host.contains(':') {
+        match host.parse::<Ipv6Addr>() {
+            Ok(_) => Ok(AddressFamily::Ipv6),
+            Err(_) => Err(format!("`{}` is not a valid IPv6 address", host)),
+        }
+    } else if host.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+        match host.parse::<Ipv4Addr>() {
+            Ok(_) => Ok(AddressFamily::Ipv4),
+            Err(_) => Err(format!("`{}` is not a valid IPv4 address", host)),
+        }
+    } else {
+        let valid_label = |label: &str| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        };
+        if host.len() <= 253 && host.trim_end_matches('.').split('.').all(valid_label) {
+            Ok(AddressFamily::Hostname)
+        } else {
+            Err(format!("`{}` is not a valid hostname", host))
+        }
+    }
+}
+
+fn parse_ports(text: &str, role: AddressRole) -> Result<Ports, String> {
+    // Digits only: `parse` would also take a sign, which only server offsets may have
+    let port = |text: &str| match text.parse::<u32>() {
+        _ if !text.bytes().all(|b| b.is_ascii_digit()) => Err(format!("`{}` is not a port number", text)),
+        Ok(port @ 1..=65535) => Ok(port as u16),
+        Ok(_) => Err(format!("port `{}` is out of range (1-65535)", text)),
+        Err(_) => Err(format!("`{}` is not a port number", text)),
+    };
+
+    match role {
+        AddressRole::Server if text.starts_with(['+', '-']) => match text[1..].parse::<u16>() {
+            Ok(offset) => Ok(Ports::Offset(if text.starts_with('-') { -(offset as i32) } else { offset as i32 })),
+            Err(_) => Err(format!("`{}` is not a port offset", text)),
+        },
+        AddressRole::Bind if text.contains('-') => {
+            let (low, high) = text.split_once('-').unwrap_or_default();
+            let (low, high) = (port(low)?, port(high)?);
+            if low > high {
+                return Err(format!("port range `{}` ends before it starts", text));
+            }
+            Ok(Ports::Range(low, high))
+        }
+        _ => port(text).map(Ports::Single),
+    }
+}
+
+impl Address {
+    /// A sentence explaining the address, e.g. for hover.
+    pub fn describe(&self) -> String {
+        let transport = self.prefix.as_deref().and_then(address_prefix).map_or("TCP", |prefix| prefix.transport);
+        let any = matches!(self.host.as_str(), "" | "*" | "::");
+
+        let what = match self.family {
+            AddressFamily::Ipv4 if any => format!("{} on any IPv4 address", transport),
+            AddressFamily::Ipv6 if any => {
+                format!("{} on any IPv6 address (and IPv4 too, unless `v6only` is set)", transport)
+            }
+            AddressFamily::Ipv4 => format!("{} over IPv4 to `{}`", transport, self.host),
+            AddressFamily::Ipv6 => format!("{} over IPv6 to `{}`", transport, self.host),
+            AddressFamily::Hostname => format!(
+                "{} to hostname `{}`, resolved when HAProxy starts or at runtime through `resolvers`",
+                transport, self.host
+            ),
+            AddressFamily::Unix => format!("Unix {} socket `{}`", transport, self.host),
+            AddressFamily::AbstractUnix => format!("Abstract Unix socket `{}` (Linux only)", self.host),
+            AddressFamily::FileDescriptor => format!("Inherited file descriptor {}", self.host),
+            AddressFamily::SocketPair => {
+                format!("Socket pair on file descriptor {}, passing connections between processes", self.host)
+            }
+            AddressFamily::ReverseHttp if self.host.is_empty() => {
+                "Reverse HTTP over the connections clients attached with `tcp-request session attach-srv`".to_string()
+            }
+            AddressFamily::ReverseHttp => format!("Reverse HTTP through the connection of server `{}`", self.host),
+        };
+        match self.ports {
+            Some(Ports::Single(port)) => format!("{}, port {}", what, port),
+            Some(Ports::Range(low, high)) => format!("{}, ports {} to {}", what, low, high),
+            Some(Ports::Offset(offset)) => {
+                format!("{}, the client's destination port {:+}", what, offset)
+            }
+            None if matches!(self.family, AddressFamily::Ipv4 | AddressFamily::Ipv6 | AddressFamily::Hostname) => {
+                format!("{}, on the port the client connected to", what)
+            }
+            None => what,
+        }
+    }
+}
+
+impl Directive {
+    /// The token holding the address of a `bind`, `server`, `server-template` or
+    /// `peer` line and how it is used. A `bind` token may list several addresses
+    /// separated by commas.
+    pub fn address_token(&self) -> Option<(&Token, AddressRole)> {
+        match self.keyword.text.as_str() {
+            "bind" | "dgram-bind" => self.args.first().map(|token| (token, AddressRole::Bind)),
+            "server" => self.args.get(1).map(|token| (token, AddressRole::Server)),
+            "peer" => self.args.get(1).map(|token| (token, AddressRole::Peer)),
+            "server-template" => self.args.get(2).map(|token| (token, AddressRole::Server)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use AddressFamily::*;
+
+    fn address(family: AddressFamily, prefix: Option<&str>, host: &str, ports: Option<Ports>) -> Address {
+        Address { prefix: prefix.map(str::to_string), family, host: host.to_string(), ports }
+    }
+
+    fn port(number: u16) -> Option<Ports> {
+        Some(Ports::Single(number))
+    }
+
+    #[test]
+    fn hosts_and_ports() {
+        let cases = [
+            ("*:80", AddressRole::Bind, address(Ipv4, None, "*", port(80))),
+            (":80", AddressRole::Bind, address(Ipv4, None, "", port(80))),
+            (":::443", AddressRole::Bind, address(Ipv6, None, "::", port(443))),
+            ("[::1]:80", AddressRole::Server, address(Ipv6, None, "::1", port(80))),
+            ("[2001:db8::1]", AddressRole::Server, address(Ipv6, None, "2001:db8::1", None)),
+            ("2001:db8::1:", AddressRole::Server, address(Ipv6, None, "2001:db8::1", None)),
+            ("10.0.0.1", AddressRole::Server, address(Ipv4, None, "10.0.0.1", None)),
+            ("app.internal:8080", AddressRole::Server, address(Hostname, None, "app.internal", port(8080))),
+            ("/run/haproxy.sock", AddressRole::Bind, address(Unix, None, "/run/haproxy.sock", None)),
+        ];
+        for (text, role, expected) in cases {
+            assert_eq!(parse_address(text, role), Ok(expected), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn prefixes() {
+        let cases = [
+            ("ipv4@10.0.0.1:80", AddressRole::Server, address(Ipv4, Some("ipv4@"), "10.0.0.1", port(80))),
+            ("quic4@:443", AddressRole::Bind, address(Ipv4, Some("quic4@"), "", Some(Ports::Single(443)))),
+            ("quic6@:443", AddressRole::Bind, address(Ipv6, Some("quic6@"), "", Some(Ports::Single(443)))),
+            ("unix@/run/x.sock", AddressRole::Bind, address(Unix, Some("unix@"), "/run/x.sock", None)),
+            ("abns@haproxy", AddressRole::Bind, address(AbstractUnix, Some("abns@"), "haproxy", None)),
+            ("fd@3", AddressRole::Bind, address(FileDescriptor, Some("fd@"), "3", None)),
+            ("sockpair@4", AddressRole::Server, address(SocketPair, Some("sockpair@"), "4", None)),
+            ("mptcp@app:80", AddressRole::Server, address(Hostname, Some("mptcp@"), "app", port(80))),
+            ("mptcp4@:80", AddressRole::Bind, address(Ipv4, Some("mptcp4@"), "", port(80))),
+            ("mptcp6@:80", AddressRole::Bind, address(Ipv6, Some("mptcp6@"), "", port(80))),
+            ("rhttp@be/srv", AddressRole::Bind, address(ReverseHttp, Some("rhttp@"), "be/srv", None)),
+            ("rhttp@", AddressRole::Server, address(ReverseHttp, Some("rhttp@"), "", None)),
+        ];
+        for (text, role, expected) in cases {
+            assert_eq!(parse_address(text, role), Ok(expected), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn every_prefix_is_looked_up_by_its_own_name() {
+        for prefix in ADDRESS_PREFIXES {
+            let found = address_prefix(&format!("{}10.0.0.1", prefix.name)).map(|found| found.name);
+            assert_eq!(found, Some(prefix.name));
+        }
+        assert!(address_prefix("cp@10.0.0.1").is_none());
+        assert!(address_prefix("10.0.0.1").is_none());
+    }
+
+    #[test]
+    fn port_ranges_and_offsets() {
+        let ports = |text, role| parse_address(text, role).map(|address| address.ports);
+        assert_eq!(ports(":8000-8010", AddressRole::Bind), Ok(Some(Ports::Range(8000, 8010))));
+        assert_eq!(ports("10.0.0.1:+1000", AddressRole::Server), Ok(Some(Ports::Offset(1000))));
+        assert_eq!(ports("10.0.0.1:-10", AddressRole::Server), Ok(Some(Ports::Offset(-10))));
+        assert_eq!(ports("10.0.0.1:65535", AddressRole::Peer), Ok(Some(Ports::Single(65535))));
+    }
+
+    #[test]
+    fn invalid_addresses() {
+        let cases = [
+            ("151.256.250.151:35800", AddressRole::Server),
+            ("2001:db8::1", AddressRole::Server),
+            ("2001:db8::1", AddressRole::Bind),
+            ("10.0.0.1", AddressRole::Bind),
+            ("*:0", AddressRole::Bind),
+            ("*:65536", AddressRole::Bind),
+            (":8010-8000", AddressRole::Bind),
+            (":+1000", AddressRole::Bind),
+            ("10.0.0.1:8000-8010", AddressRole::Server),
+            ("*:80", AddressRole::Server),
+            ("[::1", AddressRole::Server),
+            ("[::1]80", AddressRole::Server),
+            ("foo@10.0.0.1:80", AddressRole::Server),
+            ("ipv4@[::1]:80", AddressRole::Server),
+            ("unix@", AddressRole::Bind),
+            ("rhttp@", AddressRole::Bind),
+            ("mptcp4@[::1]:80", AddressRole::Server),
+            ("fd@stdin", AddressRole::Bind),
+            ("bad_host!:80", AddressRole::Server),
+            // Peers need one fixed port
+            ("10.0.0.1", AddressRole::Peer),
+            ("10.0.0.1:+1000", AddressRole::Peer),
+            ("10.0.0.1:10000-10001", AddressRole::Peer),
+        ];
+        for (text, role) in cases {
+            assert!(parse_address(text, role).is_err(), "{:?} as {:?}", text, role);
+        }
+    }
+
+    #[test]
+    fn directive_roles() {
+        let config = crate::config::ConfigFile::parse(
+            "peers p\n  peer a 10.0.0.1:10000\nbackend b\n  server s 10.0.0.2\n  server-template t 3 app:80\n",
+        );
+        let roles: Vec<(&str, AddressRole)> = config
+            .directives()
+            .filter_map(|directive| directive.address_token())
+            .map(|(token, role)| (token.text.as_str(), role))
+            .collect();
+        assert_eq!(
+            roles,
+            [("10.0.0.1:10000", AddressRole::Peer), ("10.0.0.2", AddressRole::Server), ("app:80", AddressRole::Server)]
+        );
+    }
+}
@@ -0,0 +1,492 @@
+//! The typed model of a configuration file: sections and their directives.
+
+use crate::position::{line_start, Range};
+use crate::symbols::SymbolKind;
+use crate::tokenizer::{tokenize, tokenize_continued, Token};
+
+/// The keyword a section header starts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    Global,
+    Defaults,
+    Frontend,
+    Backend,
+    Listen,
+    Peers,
+    Resolvers,
+    Userlist,
+    Mailers,
+    Cache,
+    Program,
+    HttpErrors,
+    Ring,
+    LogForward,
+    FcgiApp,
+    CrtStore,
+    Traces,
+}
+
+impl SectionKind {
+    pub const ALL: [SectionKind; 17] = [
+        SectionKind::Global,
+        SectionKind::Defaults,
+        SectionKind::Frontend,
+        SectionKind::Backend,
+        SectionKind::Listen,
+        SectionKind::Peers,
+        SectionKind::Resolvers,
+        SectionKind::Userlist,
+        SectionKind::Mailers,
+        SectionKind::Cache,
+        SectionKind::Program,
+        SectionKind::HttpErrors,
+        SectionKind::Ring,
+        SectionKind::LogForward,
+        SectionKind::FcgiApp,
+        SectionKind::CrtStore,
+        SectionKind::Traces,
+    ];
+
+    pub fn from_keyword(keyword: &str) -> Option<Self> {
+        match keyword {
+            "global" => Some(SectionKind::Global),
+            "defaults" => Some(SectionKind::Defaults),
+            "frontend" => Some(SectionKind::Frontend),
+            "backend" => Some(SectionKind::Backend),
+            "listen" => Some(SectionKind::Listen),
+            "peers" => Some(SectionKind::Peers),
+            "resolvers" => Some(SectionKind::Resolvers),
+            "userlist" => Some(SectionKind::Userlist),
+            "mailers" => Some(SectionKind::Mailers),
+            "cache" => Some(SectionKind::Cache),
+            "program" => Some(SectionKind::Program),
+            "http-errors" => Some(SectionKind::HttpErrors),
+            "ring" => Some(SectionKind::Ring),
+            "log-forward" => Some(SectionKind::LogForward),
+            "fcgi-app" => Some(SectionKind::FcgiApp),
+            "crt-store" => Some(SectionKind::CrtStore),
+            "traces" => Some(SectionKind::Traces),
+            _ => None,
+        }
+    }
+
+    pub fn keyword(self) -> &'static str {
+        match self {
+            SectionKind::Global => "global",
+            SectionKind::Defaults => "defaults",
+            SectionKind::Frontend => "frontend",
+            SectionKind::Backend => "backend",
+            SectionKind::Listen => "listen",
+            SectionKind::Peers => "peers",
+            SectionKind::Resolvers => "resolvers",
+            SectionKind::Userlist => "userlist",
+            SectionKind::Mailers => "mailers",
+            SectionKind::Cache => "cache",
+            SectionKind::Program => "program",
+            SectionKind::HttpErrors => "http-errors",
+            SectionKind::Ring => "ring",
+            SectionKind::LogForward => "log-forward",
+            SectionKind::FcgiApp => "fcgi-app",
+            SectionKind::CrtStore => "crt-store",
+            SectionKind::Traces => "traces",
+        }
+    }
+
+    /// The navigable symbol a named section of this kind declares.
+    pub fn symbol_kind(self) -> Option<SymbolKind> {
+        match self {
+            SectionKind::Frontend => Some(SymbolKind::Frontend),
+            SectionKind::Backend => Some(SymbolKind::Backend),
+            SectionKind::Listen => Some(SymbolKind::Listen),
+            _ => None,
+        }
+    }
+}
+
+/// One config line: a keyword followed by its arguments.
+#[derive(Debug, Clone)]
+pub struct Directive {
+    pub keyword: Token,
+    pub args: Vec<Token>,
+    /// Index of the enclosing section in `ConfigFile::sections`, `None` before the first section.
+    pub section: Option<usize>,
+}
+
+impl Directive {
+    pub fn line(&self) -> u32 {
+        self.keyword.range.start.line
+    }
+
+    /// Moves the directive by `lines` and `bytes` and renumbers its section by `sections`.
+    pub(crate) fn shift(&mut self, lines: i64, bytes: i64, sections: i64) {
+        self.keyword.shift(lines, bytes);
+        for arg in &mut self.args {
+            arg.shift(lines, bytes);
+        }
+        self.section = self.section.map(|index| (index as i64 + sections) as usize);
+    }
+
+    /// Whether the directive is written on `line`, continuation lines included.
+    pub fn spans_line(&self, line: u32) -> bool {
+        self.line() <= line && line <= self.range().end.line
+    }
+
+    pub fn range(&self) -> Range {
+        let end = self.args.last().unwrap_or(&self.keyword).range.end.clone();
+        Range { start: self.keyword.range.start.clone(), end }
+    }
+
+    /// The `if`/`unless` keyword of the directive and the condition following it.
+    pub fn condition(&self) -> Option<(&Token, &[Token])> {
+        let index = self.args.iter().position(|arg| arg.text == "if" || arg.text == "unless")?;
+        Some((&self.args[index], &self.args[index + 1..]))
+    }
+}
+
+/// One text change, in byte offsets and line numbers of the text before and after it.
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub start_byte: usize,
+    pub old_end_byte: usize,
+    pub new_end_byte: usize,
+    pub start_line: u32,
+    pub old_end_line: u32,
+    pub new_end_line: u32,
+}
+
+/// The sections [`ConfigFile::reparse`] parsed again: those at `old` before the edit
+/// are now the ones at `new`. Sections before them are unchanged and the ones after
+/// only moved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reparsed {
+    pub old: std::ops::Range<usize>,
+    pub new: std::ops::Range<usize>,
+}
+
+/// A section header and the directives up to the next one.
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub kind: SectionKind,
+    pub header: Directive,
+    pub directives: Vec<Directive>,
+    /// Last line belonging to the section, i.e. the line before the next section header.
+    pub end_line: u32,
+}
+
+impl Section {
+    pub fn name(&self) -> Option<&Token> {
+        // `defaults from other` inherits without being named itself
+        self.header.args.first().filter(|name| name.text != "from")
+    }
+
+    pub fn contains_line(&self, line: u32) -> bool {
+        self.header.line() <= line && line <= self.end_line
+    }
+
+    pub(crate) fn shift(&mut self, lines: i64, bytes: i64, sections: i64) {
+        self.header.shift(lines, bytes, sections);
+        for directive in &mut self.directives {
+            directive.shift(lines, bytes, sections);
+        }
+        self.end_line = (self.end_line as i64 + lines) as u32;
+    }
+}
+
+/// Typed model of a whole configuration file.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigFile {
+    /// Directives that appear before the first section header.
+    pub preamble: Vec<Directive>,
+    pub sections: Vec<Section>,
+}
+
+impl ConfigFile {
+    /// Parses `content` into sections and directives.
+    pub fn parse(content: &str) -> Self {
+        let mut config = ConfigFile::default();
+        for tokens in tokenize(content) {
+            config.push_line(tokens);
+        }
+
+        if let Some(last) = config.sections.last_mut() {
+            last.end_line = content.lines().count().saturating_sub(1) as u32;
+        }
+        config
+    }
+
+    /// Appends one logical line, opening a new section if it is a section header.
+    fn push_line(&mut self, tokens: Vec<Token>) {
+        let mut tokens = tokens.into_iter();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => return,
+        };
+        let line_num = keyword.range.start.line;
+
+        if let Some(kind) = SectionKind::from_keyword(&keyword.text) {
+            if let Some(previous) = self.sections.last_mut() {
+                previous.end_line = line_num.saturating_sub(1);
+            }
+            let header = Directive {
+                keyword,
+                args: tokens.collect(),
+                section: Some(self.sections.len()),
+            };
+            self.sections.push(Section { kind, header, directives: Vec::new(), end_line: line_num });
+            return;
+        }
+
+        let section = self.sections.len().checked_sub(1);
+        let directive = Directive { keyword, args: tokens.collect(), section };
+        match section {
+            Some(index) => self.sections[index].directives.push(directive),
+            None => self.preamble.push(directive),
+        }
+    }
+
+    /// Updates the model after `edit` was applied to produce `text`, re-parsing only
+    /// the sections the edit touched and shifting the ones after them. Returns which
+    /// sections were re-parsed, or `None` if the whole text was.
+    pub fn reparse(&mut self, text: &str, edit: &Edit) -> Option<Reparsed> {
+        let start_line = edit.start_line;
+        let old_end_line = edit.old_end_line;
+        let line_delta = edit.new_end_line as i64 - edit.old_end_line as i64;
+        let byte_delta = edit.new_end_byte as i64 - edit.old_end_byte as i64;
+
+        // Start from a header the edit leaves alone, so that directives losing their
+        // own header still land in the section before it
+        let first = self.sections.iter().rposition(|section| section.header.line() < start_line);
+        let last = self.sections.iter().rposition(|section| section.header.line() <= old_end_line);
+        let (first, last) = match (first, last) {
+            (Some(first), Some(last)) => (first, last.max(first)),
+            _ => {
+                *self = ConfigFile::parse(text);
+                return None;
+            }
+        };
+
+        let region_start_line = self.sections[first].header.line();
+        let region_start = line_start(text, self.sections[first].header.keyword.span.start);
+        // A continuation at the end of the region turns the next header into an
+        // argument, so the region grows until its last line ends
+        let mut last = last;
+        let (region_end, lines) = loop {
+            let region_end = match self.sections.get(last + 1) {
+                Some(next) => line_start(text, (next.header.keyword.span.start as i64 + byte_delta) as usize),
+                None => text.len(),
+            };
+            match tokenize_continued(&text[region_start..region_end]) {
+                (_, true) if last + 1 < self.sections.len() => last += 1,
+                (lines, _) => break (region_end, lines),
+            }
+        };
+
+        let mut region = ConfigFile::default();
+        for mut tokens in lines {
+            for token in &mut tokens {
+                token.shift(region_start_line as i64, region_start as i64);
+            }
+            region.push_line(tokens);
+        }
+        if !region.preamble.is_empty() {
+            *self = ConfigFile::parse(text);
+            return None;
+        }
+        if let Some(region_last) = region.sections.last_mut() {
+            let region_lines = text[region_start..region_end].lines().count().max(1) as u32;
+            region_last.end_line = region_start_line + region_lines - 1;
+        }
+
+        let tail: Vec<Section> = self.sections.drain(last + 1..).collect();
+        self.sections.truncate(first);
+        let reparsed = Reparsed { old: first..last + 1, new: first..first + region.sections.len() };
+        let section_delta = reparsed.new.end as i64 - reparsed.old.end as i64;
+
+        for mut section in region.sections {
+            section.shift(0, 0, first as i64);
+            self.sections.push(section);
+        }
+        for mut section in tail {
+            section.shift(line_delta, byte_delta, section_delta);
+            self.sections.push(section);
+        }
+        Some(reparsed)
+    }
+
+    /// The defaults section the section at `index` inherits from: the one named by
+    /// `from <name>` on its header, otherwise the last defaults section before it.
+    pub fn defaults_for(&self, index: usize) -> Option<usize> {
+        let args = &self.sections[index].header.args;
+        let preceding = &self.sections[..index];
+
+        match args.iter().position(|arg| arg.text == "from") {
+            Some(from) => {
+                let name = &args.get(from + 1)?.text;
+                preceding.iter().rposition(|section| {
+                    section.kind == SectionKind::Defaults && section.name().map(|n| &n.text) == Some(name)
+                })
+            }
+            None => preceding.iter().rposition(|section| section.kind == SectionKind::Defaults),
+        }
+    }
+
+    /// Sections searched, in order, when resolving a `kind` name used in `section`.
+    pub fn lookup_scopes(&self, section: Option<usize>, kind: &SymbolKind) -> Vec<usize> {
+        let mut scopes: Vec<usize> = section.into_iter().collect();
+        if *kind == SymbolKind::Acl {
+            scopes.extend(section.and_then(|index| self.defaults_for(index)));
+        }
+        scopes
+    }
+
+    pub fn section_at(&self, line: u32) -> Option<&Section> {
+        self.sections.iter().find(|section| section.contains_line(line))
+    }
+
+    /// The directive (or section header) written on `line`.
+    pub fn directive_at(&self, line: u32) -> Option<&Directive> {
+        match self.section_at(line) {
+            Some(section) => std::iter::once(&section.header)
+                .chain(section.directives.iter())
+                .find(|directive| directive.spans_line(line)),
+            None => self.preamble.iter().find(|directive| directive.spans_line(line)),
+        }
+    }
+
+    /// Every directive in document order, section headers included.
+    pub fn directives(&self) -> impl Iterator<Item = &Directive> {
+        self.preamble.iter().chain(
+            self.sections
+                .iter()
+                .flat_map(|section| std::iter::once(&section.header).chain(section.directives.iter())),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::LineIndex;
+
+    /// `text` with `start..end` replaced by `new_text`, and the matching edit.
+    fn apply(text: &str, start: usize, end: usize, new_text: &str) -> (String, Edit) {
+        let line = |text: &str, offset: usize| text[..offset].matches('\n').count() as u32;
+        let edited = format!("{}{}{}", &text[..start], new_text, &text[end..]);
+        let new_end = start + new_text.len();
+        let edit = Edit {
+            start_byte: start,
+            old_end_byte: end,
+            new_end_byte: new_end,
+            start_line: line(text, start),
+            old_end_line: line(text, end),
+            new_end_line: line(&edited, new_end),
+        };
+        (edited, edit)
+    }
+
+    fn assert_same_model(incremental: &ConfigFile, text: &str) {
+        assert_eq!(format!("{:?}", incremental), format!("{:?}", ConfigFile::parse(text)), "after edit to {:?}", text);
+    }
+
+    #[test]
+    fn defaults_for_follows_from_or_the_last_defaults() {
+        let config = ConfigFile::parse(
+            "defaults a\n  mode http\nfrontend f1\ndefaults b\nbackend b1\nlisten l1 from a\n\
+             frontend f2 from missing\n",
+        );
+        let defaults: Vec<Option<usize>> = (0..config.sections.len()).map(|index| config.defaults_for(index)).collect();
+        assert_eq!(defaults, [None, Some(0), Some(0), Some(2), Some(0), None]);
+
+        // `from` only sees defaults sections above it
+        let config = ConfigFile::parse("frontend f from later\ndefaults later\n");
+        assert_eq!(config.defaults_for(0), None);
+    }
+
+    #[test]
+    fn only_acls_are_looked_up_in_defaults() {
+        let config = ConfigFile::parse("defaults d\nfrontend f\nbackend b\n");
+        assert_eq!(config.lookup_scopes(Some(1), &SymbolKind::Acl), [1, 0]);
+        assert_eq!(config.lookup_scopes(Some(2), &SymbolKind::Server), [2]);
+        assert_eq!(config.lookup_scopes(Some(0), &SymbolKind::Acl), [0]);
+        assert_eq!(config.lookup_scopes(None, &SymbolKind::Acl), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn continuation_turns_the_next_header_into_an_argument() {
+        let text = "backend a\n  server s 1.2.3.4:80\nbackend b\n  server t 1.2.3.5:80\n";
+        let mut config = ConfigFile::parse(text);
+        let offset = text.find(":80").unwrap() + 3;
+
+        let (text, edit) = apply(text, offset, offset, " \\");
+        config.reparse(&text, &edit);
+        assert_eq!(config.sections.len(), 1);
+        assert_same_model(&config, &text);
+
+        let (text, edit) = apply(&text, offset, offset + 2, "");
+        config.reparse(&text, &edit);
+        assert_eq!(config.sections.len(), 2);
+        assert_same_model(&config, &text);
+    }
+
+    #[test]
+    fn reparse_matches_a_full_parse() {
+        const BASE: &str = "global\n  maxconn 100\ndefaults base\n  mode http\nfrontend fe from base\n  bind :80 \\\n    \
+            ssl crt /x.pem\n  use_backend \"be api\" if is_api # comment\n  acl is_api path_beg /api\nbackend be\n  \
+            server s1 10.0.0.1:80 \\\n    check\n\nbackend \"be api\"\n  server s2 10.0.0.2:80\nlisten l\n  bind :81\n";
+        const SNIPPETS: &[&str] = &[
+            "backend b\n",
+            "frontend f\n",
+            "  server s 10.0.0.1:80\n",
+            "  acl a path /x # c\n",
+            "\\\n",
+            " \\",
+            "\\",
+            "\n",
+            "\r\n",
+            "#",
+            "\"",
+            "'",
+            "x",
+            " ",
+        ];
+
+        // xorshift64, so that a failure reproduces
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut random = |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
+        };
+
+        let headers = |sections: &[Section]| -> Vec<String> {
+            let header = |section: &Section| format!("{:?}", (&section.header.keyword.text, section.directives.len()));
+            sections.iter().map(header).collect()
+        };
+
+        for _ in 0..2000 {
+            let mut text = BASE.to_string();
+            let mut config = ConfigFile::parse(&text);
+            let mut lines = LineIndex::new(&text);
+            for _ in 0..1 + random(6) {
+                let start = random(text.len() + 1);
+                let end = (start + [0, 0, 1, 5, 20][random(5)]).min(text.len());
+                let new_text = if random(10) < 7 { SNIPPETS[random(SNIPPETS.len())] } else { "" };
+
+                let (edited, edit) = apply(&text, start, end, new_text);
+                let before = config.sections.clone();
+                let reparsed = config.reparse(&edited, &edit);
+                lines.edit(&edited, &edit);
+                text = edited;
+                assert_same_model(&config, &text);
+                assert_eq!(lines, LineIndex::new(&text), "line index after edit to {:?}", text);
+
+                // Sections outside the reported ones are the same, only moved
+                if let Some(Reparsed { old, new }) = reparsed {
+                    assert_eq!(headers(&before[..old.start]), headers(&config.sections[..new.start]));
+                    assert_eq!(headers(&before[old.end..]), headers(&config.sections[new.end..]));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,815 @@
+//! Problems found in a configuration, shared by the language server and `haproxy-lsp check`.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::addresses::{address_prefix, parse_address};
+use crate::config::{ConfigFile, Directive, SectionKind};
+use crate::keywords::{section_since, Keyword, Version};
+use crate::position::Range;
+use crate::symbols::{collect_symbols_with, Symbol, SymbolIndex, SymbolKind, TokenRole, PREDEFINED_ACLS};
+use crate::tokenizer::Token;
+
+/// Classifies the tokens of a directive in the file at the given URI.
+type FileSymbolTokens<'a> = dyn Fn(&str, &Directive) -> Vec<(Token, TokenRole)> + 'a;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Information => "info",
+            Severity::Hint => "hint",
+        }
+    }
+}
+
+/// Extra meaning an editor can render, e.g. by fading unused code out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticTag {
+    Unnecessary,
+    Deprecated,
+}
+
+/// Another location that explains a diagnostic, such as the first of two definitions.
+#[derive(Debug, Clone)]
+pub struct RelatedInformation {
+    pub uri: String,
+    pub range: Range,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub range: Range,
+    pub severity: Severity,
+    /// Stable identifier of the rule that produced the diagnostic, e.g. `outside-section`.
+    pub code: &'static str,
+    pub message: String,
+    pub tags: Vec<DiagnosticTag>,
+    pub related: Vec<RelatedInformation>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &'static str, range: Range, message: String) -> Self {
+        Diagnostic { range, severity, code, message, tags: Vec::new(), related: Vec::new() }
+    }
+
+    fn tagged(mut self, tag: DiagnosticTag) -> Self {
+        self.tags.push(tag);
+        self
+    }
+
+    fn related(mut self, uri: &str, range: &Range, message: &str) -> Self {
+        self.related.push(RelatedInformation { uri: uri.to_string(), range: range.clone(), message: message.to_string() });
+        self
+    }
+}
+
+/// Every problem found in `config`, the file at `uri`. `workspace` holds all files
+/// checked together by URI, in load order and this one included, so names may be
+/// defined or used in another file. With a `version`, keywords that release doesn't
+/// know are reported too.
+pub fn diagnose(
+    uri: &str,
+    config: &ConfigFile,
+    workspace: &[(&str, &ConfigFile)],
+    version: Option<Version>,
+) -> Vec<Diagnostic> {
+    diagnose_with(uri, config, workspace, version, &|_, directive| directive.symbol_tokens())
+}
+
+/// Like [`diagnose`], with `symbol_tokens` deciding which tokens of a directive in
+/// the file at the given URI declare or use a name, as for [`collect_symbols_with`].
+pub fn diagnose_with(
+    uri: &str,
+    config: &ConfigFile,
+    workspace: &[(&str, &ConfigFile)],
+    version: Option<Version>,
+    symbol_tokens: &FileSymbolTokens<'_>,
+) -> Vec<Diagnostic> {
+    let own_tokens = |directive: &Directive| symbol_tokens(uri, directive);
+    let symbols = collect_symbols_with(uri, config, &own_tokens);
+    let mut diagnostics = Vec::new();
+    outside_section(config, &mut diagnostics);
+    misplaced_keywords(config, &mut diagnostics);
+    deprecated_keywords(config, version, &mut diagnostics);
+    invalid_values(config, &mut diagnostics);
+    invalid_addresses(config, &mut diagnostics);
+    if let Some(version) = version {
+        unsupported_keywords(config, version, &mut diagnostics);
+    }
+    undefined_backends(config, workspace, &own_tokens, &mut diagnostics);
+    undefined_acls(config, &symbols, &own_tokens, &mut diagnostics);
+    unused_acls(&symbols, &mut diagnostics);
+    unused_backends(config, workspace, symbol_tokens, &mut diagnostics);
+    unused_servers(config, &symbols, &mut diagnostics);
+    duplicate_proxies(uri, workspace, &mut diagnostics);
+    duplicate_servers(uri, config, &mut diagnostics);
+    duplicate_acls(uri, config, &mut diagnostics);
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.range.start.line, diagnostic.range.start.character));
+    diagnostics
+}
+
+/// HAProxy refuses any keyword before the first section header.
+fn outside_section(config: &ConfigFile, diagnostics: &mut Vec<Diagnostic>) {
+    for directive in &config.preamble {
+        diagnostics.push(Diagnostic::new(
+            Severity::Error,
+            "outside-section",
+            directive.keyword.range.clone(),
+            format!("`{}` appears before any section", directive.keyword.text),
+        ));
+    }
+}
+
+/// Known keywords in a section that doesn't accept them. A proxy keyword in the
+/// wrong kind of proxy, like `server` in a frontend, is only ignored with a warning
+/// by HAProxy; anything else fails to load.
+fn misplaced_keywords(config: &ConfigFile, diagnostics: &mut Vec<Diagnostic>) {
+    const PROXIES: [SectionKind; 3] = [SectionKind::Frontend, SectionKind::Listen, SectionKind::Backend];
+
+    for section in &config.sections {
+        for directive in &section.directives {
+            let (keyword, range) = match directive.keyword_entry() {
+                Some(entry) => entry,
+                None => continue,
+            };
+            if keyword.allowed_in(section.kind) {
+                continue;
+            }
+            let severity = if PROXIES.contains(&section.kind) && PROXIES.iter().any(|kind| keyword.allowed_in(*kind)) {
+                Severity::Warning
+            } else {
+                Severity::Error
+            };
+            let allowed: Vec<&str> = keyword.sections.kinds().map(SectionKind::keyword).collect();
+            diagnostics.push(Diagnostic::new(
+                severity,
+                "misplaced-keyword",
+                range,
+                format!(
+                    "`{}` is not allowed in a {} section (allowed in: {})",
+                    keyword.name,
+                    section.kind.keyword(),
+                    allowed.join(", ")
+                ),
+            ));
+        }
+    }
+}
+
+/// Time, size, integer and percentage arguments HAProxy would refuse at load time.
+fn invalid_values(config: &ConfigFile, diagnostics: &mut Vec<Diagnostic>) {
+    for section in &config.sections {
+        for directive in &section.directives {
+            for (name, token, kind) in directive.typed_values() {
+                // `${VAR}` is only known once HAProxy starts
+                if token.text.contains("${") {
+                    continue;
+                }
+                if let Err(message) = kind.check(&name, &token.text) {
+                    diagnostics.push(Diagnostic::new(Severity::Error, "invalid-value", token.range.clone(), message));
+                }
+            }
+        }
+    }
+}
+
+/// Malformed `bind` and `server` addresses and out-of-range ports.
+fn invalid_addresses(config: &ConfigFile, diagnostics: &mut Vec<Diagnostic>) {
+    for section in &config.sections {
+        for directive in &section.directives {
+            let (token, role) = match directive.address_token() {
+                Some(address) => address,
+                None => continue,
+            };
+            if token.text.contains("${") {
+                continue;
+            }
+            if let Some(message) = token.text.split(',').find_map(|address| parse_address(address, role).err()) {
+                diagnostics.push(Diagnostic::new(Severity::Error, "invalid-address", token.range.clone(), message));
+            }
+        }
+    }
+}
+
+/// "`reqadd` was removed in HAProxy 2.1, use `http-request add-header` instead"
+fn removal_message(keyword: &Keyword, removed: Version) -> String {
+    let mut message = format!("`{}` was removed in HAProxy {}", keyword.name, removed);
+    if let Some(replacement) = keyword.replaced_by {
+        message.push_str(&format!(", use `{}` instead", replacement));
+    }
+    message
+}
+
+/// Keywords a later release removed, as long as `version` doesn't target that
+/// release already; [`unsupported_keywords`] reports them as errors then.
+fn deprecated_keywords(config: &ConfigFile, version: Option<Version>, diagnostics: &mut Vec<Diagnostic>) {
+    for section in &config.sections {
+        for directive in &section.directives {
+            let (keyword, range) = match directive.keyword_entry() {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let removed = match keyword.removed {
+                Some(removed) if version.is_none_or(|version| version < removed) => removed,
+                _ => continue,
+            };
+            diagnostics.push(
+                Diagnostic::new(Severity::Warning, "deprecated-keyword", range, removal_message(keyword, removed))
+                    .tagged(DiagnosticTag::Deprecated),
+            );
+        }
+    }
+}
+
+/// Sections, keywords and address prefixes that `version` doesn't accept yet or
+/// anymore.
+fn unsupported_keywords(config: &ConfigFile, version: Version, diagnostics: &mut Vec<Diagnostic>) {
+    let mut report = |range: &Range, message: String| {
+        diagnostics.push(Diagnostic::new(
+            Severity::Error,
+            "unsupported-keyword",
+            range.clone(),
+            format!("{} (targeting HAProxy {})", message, version),
+        ));
+    };
+
+    for section in &config.sections {
+        let since = section_since(section.kind);
+        if version < since {
+            report(
+                &section.header.keyword.range,
+                format!("`{}` sections require HAProxy {}", section.kind.keyword(), since),
+            );
+        }
+
+        for directive in &section.directives {
+            if let Some((keyword, range)) = directive.keyword_entry() {
+                match keyword.removed {
+                    Some(removed) if version >= removed => report(&range, removal_message(keyword, removed)),
+                    _ if version < keyword.since => {
+                        report(&range, format!("`{}` requires HAProxy {}", keyword.name, keyword.since))
+                    }
+                    _ => {}
+                }
+            }
+
+            if !matches!(directive.keyword.text.as_str(), "bind" | "server" | "dgram-bind") {
+                continue;
+            }
+            for arg in &directive.args {
+                if let Some(prefix) = address_prefix(&arg.text).filter(|prefix| version < prefix.since) {
+                    report(&arg.range, format!("`{}` addresses require HAProxy {}", prefix.name, prefix.since));
+                }
+            }
+        }
+    }
+}
+
+/// `use_backend` and `default_backend` targets that no `backend` or `listen` section declares.
+fn undefined_backends(
+    config: &ConfigFile,
+    workspace: &[(&str, &ConfigFile)],
+    symbol_tokens: &dyn Fn(&Directive) -> Vec<(Token, TokenRole)>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let backends: HashSet<&str> = workspace
+        .iter()
+        .flat_map(|(_, config)| &config.sections)
+        .filter(|section| section.kind.symbol_kind().is_some_and(|kind| kind.satisfies(&SymbolKind::Backend)))
+        .filter_map(|section| section.name().map(|name| name.text.as_str()))
+        .collect();
+
+    for directive in config.directives() {
+        for (token, role) in symbol_tokens(directive) {
+            if !matches!(role, TokenRole::Reference(SymbolKind::Backend, _)) {
+                continue;
+            }
+            // `%[...]` picks the backend at runtime and `${...}` at startup
+            if token.text.contains("%[") || token.text.contains("${") {
+                continue;
+            }
+            if !backends.contains(token.text.as_str()) {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Error,
+                    "undefined-backend",
+                    token.range,
+                    format!("backend `{}` is not defined", token.text),
+                ));
+            }
+        }
+    }
+}
+
+/// ACL names in `if`/`unless` conditions that are neither predefined nor declared
+/// where HAProxy looks them up: the rule's own section and the defaults it inherits.
+fn undefined_acls(
+    config: &ConfigFile,
+    symbols: &[Symbol],
+    symbol_tokens: &dyn Fn(&Directive) -> Vec<(Token, TokenRole)>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let index = SymbolIndex::new(symbols);
+    for directive in config.directives() {
+        for (token, role) in symbol_tokens(directive) {
+            if !matches!(role, TokenRole::Reference(SymbolKind::Acl, _)) || PREDEFINED_ACLS.contains(&token.text.as_str()) {
+                continue;
+            }
+            let scopes = config.lookup_scopes(directive.section, &SymbolKind::Acl);
+            if index.resolve(&token.text, &SymbolKind::Acl, &scopes).is_none() {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Error,
+                    "undefined-acl",
+                    token.range,
+                    format!("ACL `{}` is not defined", token.text),
+                ));
+            }
+        }
+    }
+}
+
+/// ACLs no condition refers to. Lines repeating an ACL name extend the same ACL,
+/// so a use of any of them counts for all.
+fn unused_acls(symbols: &[Symbol], diagnostics: &mut Vec<Diagnostic>) {
+    let acls: Vec<&Symbol> = symbols.iter().filter(|symbol| symbol.kind == SymbolKind::Acl).collect();
+    let used: HashSet<(&str, Option<usize>)> = acls
+        .iter()
+        .filter(|acl| !acl.references.is_empty())
+        .map(|acl| (acl.name.as_str(), acl.section))
+        .collect();
+
+    for acl in acls {
+        if !used.contains(&(acl.name.as_str(), acl.section)) {
+            diagnostics.push(
+                Diagnostic::new(
+                    Severity::Warning,
+                    "unused-acl",
+                    acl.range.clone(),
+                    format!("ACL `{}` is never used", acl.name),
+                )
+                .tagged(DiagnosticTag::Unnecessary),
+            );
+        }
+    }
+}
+
+/// Backends no `use_backend` or `default_backend` in the workspace targets, and
+/// nothing uses as a stick table or tracks servers of. A dynamic `%[...]` target
+/// may pick any backend, so it turns the check off. Backends holding a
+/// `stick-table` are skipped too, since sample fetches like `sc_http_req_rate(0,<table>)`
+/// may read them.
+fn unused_backends(
+    config: &ConfigFile,
+    workspace: &[(&str, &ConfigFile)],
+    symbol_tokens: &FileSymbolTokens<'_>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut targets = HashSet::new();
+    for (uri, config) in workspace {
+        match backend_uses_with(config.directives(), &|directive| symbol_tokens(uri, directive)) {
+            Some(uses) => targets.extend(uses),
+            None => return,
+        }
+    }
+
+    for section in config.sections.iter().filter(|section| section.kind == SectionKind::Backend) {
+        if section.directives.iter().any(|directive| directive.keyword.text == "stick-table") {
+            continue;
+        }
+        if let Some(name) = section.name().filter(|name| !targets.contains(&name.text)) {
+            diagnostics.push(
+                Diagnostic::new(
+                    Severity::Warning,
+                    "unused-backend",
+                    name.range.clone(),
+                    format!("backend `{}` is never targeted by `use_backend` or `default_backend`", name.text),
+                )
+                .tagged(DiagnosticTag::Unnecessary),
+            );
+        }
+    }
+}
+
+/// The backend names `config` routes to, names as a stick table (`table <name>`)
+/// or tracks servers in (`track <backend>/<server>`), or `None` when a dynamic
+/// target may pick any backend. This is all other files' diagnostics read of its
+/// directives.
+pub fn backend_uses(config: &ConfigFile) -> Option<HashSet<String>> {
+    backend_uses_with(config.directives(), &Directive::symbol_tokens)
+}
+
+/// Like [`backend_uses`] for some of a file's `directives`, e.g. one section, with
+/// targets classified by `symbol_tokens`.
+pub fn backend_uses_with<'a>(
+    directives: impl IntoIterator<Item = &'a Directive>,
+    symbol_tokens: &dyn Fn(&Directive) -> Vec<(Token, TokenRole)>,
+) -> Option<HashSet<String>> {
+    let mut targets = HashSet::new();
+    for directive in directives {
+        for pair in directive.args.windows(2) {
+            match pair[0].text.as_str() {
+                "table" => {
+                    targets.insert(pair[1].text.clone());
+                }
+                "track" => targets.extend(pair[1].text.split_once('/').map(|(backend, _)| backend.to_string())),
+                _ => {}
+            }
+        }
+        for (token, role) in symbol_tokens(directive) {
+            if matches!(role, TokenRole::Reference(SymbolKind::Backend, _)) {
+                if token.text.contains("%[") || token.text.contains("${") {
+                    return None;
+                }
+                targets.insert(token.text);
+            }
+        }
+    }
+    Some(targets)
+}
+
+/// Servers with `weight 0` only get traffic through `use-server`, so one that no
+/// rule selects and no other server tracks is dead.
+fn unused_servers(config: &ConfigFile, symbols: &[Symbol], diagnostics: &mut Vec<Diagnostic>) {
+    // `track [<backend>/]<server>`, resolved to (backend section, server name)
+    let mut tracked = HashSet::new();
+    for (index, section) in config.sections.iter().enumerate() {
+        for directive in section.directives.iter().filter(|directive| directive.keyword.text == "server") {
+            let target = directive.args.windows(2).find(|pair| pair[0].text == "track").map(|pair| &pair[1].text);
+            let (backend, server) = match target.map(|target| target.split_once('/')) {
+                Some(Some((backend, server))) => {
+                    let backend = config.sections.iter().position(|section| {
+                        section.kind.symbol_kind().is_some() && section.name().is_some_and(|name| name.text == backend)
+                    });
+                    (backend, server)
+                }
+                Some(None) => (Some(index), target.map(String::as_str).unwrap_or_default()),
+                None => continue,
+            };
+            tracked.extend(backend.map(|backend| (backend, server.to_string())));
+        }
+    }
+
+    let selected: HashSet<(usize, &str)> = symbols
+        .iter()
+        .filter(|symbol| symbol.kind == SymbolKind::Server && !symbol.references.is_empty())
+        .filter_map(|symbol| Some((symbol.section?, symbol.name.as_str())))
+        .collect();
+
+    for (index, section) in config.sections.iter().enumerate() {
+        for directive in section.directives.iter().filter(|directive| directive.keyword.text == "server") {
+            let name = match directive.args.first() {
+                Some(name) => name,
+                None => continue,
+            };
+            let weight_zero = directive.args.windows(2).any(|pair| pair[0].text == "weight" && pair[1].text == "0");
+            let used = selected.contains(&(index, name.text.as_str())) || tracked.contains(&(index, name.text.clone()));
+
+            if weight_zero && !used {
+                diagnostics.push(
+                    Diagnostic::new(
+                        Severity::Hint,
+                        "unused-server",
+                        name.range.clone(),
+                        format!("server `{}` has weight 0 and no `use-server` rule selects it", name.text),
+                    )
+                    .tagged(DiagnosticTag::Unnecessary),
+                );
+            }
+        }
+    }
+}
+
+/// Proxies whose names clash. A frontend and a backend may share a name since one
+/// only accepts and the other only forwards traffic, but a `listen` section does
+/// both and so collides with any proxy.
+fn duplicate_proxies(uri: &str, workspace: &[(&str, &ConfigFile)], diagnostics: &mut Vec<Diagnostic>) {
+    let mut seen: HashMap<&str, Vec<(&str, SectionKind, &Token)>> = HashMap::new();
+
+    for &(file, config) in workspace {
+        for section in config.sections.iter().filter(|section| section.kind.symbol_kind().is_some()) {
+            let name = match section.name() {
+                Some(name) => name,
+                None => continue,
+            };
+            let earlier = seen.entry(name.text.as_str()).or_default();
+            let clash = earlier.iter().find(|(_, kind, _)| {
+                *kind == section.kind || *kind == SectionKind::Listen || section.kind == SectionKind::Listen
+            });
+
+            if let Some((first_uri, first_kind, first)) = clash.filter(|_| file == uri) {
+                diagnostics.push(
+                    Diagnostic::new(
+                        Severity::Error,
+                        "duplicate-proxy",
+                        name.range.clone(),
+                        format!(
+                            "{} `{}` has the same name as an earlier {}",
+                            section.kind.keyword(),
+                            name.text,
+                            first_kind.keyword()
+                        ),
+                    )
+                    .related(first_uri, &first.range, "first defined here"),
+                );
+            }
+            earlier.push((file, section.kind, name));
+        }
+    }
+}
+
+/// Servers declared twice in the same backend.
+fn duplicate_servers(uri: &str, config: &ConfigFile, diagnostics: &mut Vec<Diagnostic>) {
+    for section in &config.sections {
+        let mut seen: HashMap<&str, &Token> = HashMap::new();
+        for directive in section.directives.iter().filter(|directive| directive.keyword.text == "server") {
+            let name = match directive.args.first() {
+                Some(name) => name,
+                None => continue,
+            };
+            match seen.get(name.text.as_str()) {
+                Some(first) => diagnostics.push(
+                    Diagnostic::new(
+                        Severity::Error,
+                        "duplicate-server",
+                        name.range.clone(),
+                        format!("server `{}` is already declared in this backend", name.text),
+                    )
+                    .related(uri, &first.range, "first declared here"),
+                ),
+                None => {
+                    seen.insert(name.text.as_str(), name);
+                }
+            }
+        }
+    }
+}
+
+/// ACL names declared more than once in a section. That is legal, HAProxy matches
+/// if any of the declarations does, but easily mistaken for a redefinition.
+fn duplicate_acls(uri: &str, config: &ConfigFile, diagnostics: &mut Vec<Diagnostic>) {
+    for section in &config.sections {
+        let mut seen: HashMap<&str, &Token> = HashMap::new();
+        for directive in section.directives.iter().filter(|directive| directive.keyword.text == "acl") {
+            let name = match directive.args.first() {
+                Some(name) => name,
+                None => continue,
+            };
+            match seen.get(name.text.as_str()) {
+                Some(first) => diagnostics.push(
+                    Diagnostic::new(
+                        Severity::Information,
+                        "duplicate-acl",
+                        name.range.clone(),
+                        format!("ACL `{}` is declared again; it matches if either declaration matches", name.text),
+                    )
+                    .related(uri, &first.range, "first declared here"),
+                ),
+                None => {
+                    seen.insert(name.text.as_str(), name);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The codes and lines of the diagnostics of `text` as a single file.
+    fn codes(text: &str) -> Vec<(&'static str, u32)> {
+        let config = ConfigFile::parse(text);
+        diagnose("haproxy.cfg", &config, &[("haproxy.cfg", &config)], None)
+            .iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.range.start.line))
+            .collect()
+    }
+
+    /// The diagnostics with `code` of the last of `files`, loaded in order.
+    fn found(code: &str, files: &[&str]) -> Vec<Diagnostic> {
+        let configs: Vec<ConfigFile> = files.iter().map(|text| ConfigFile::parse(text)).collect();
+        let uris = ["a.cfg", "b.cfg", "c.cfg"];
+        let workspace: Vec<(&str, &ConfigFile)> = uris.iter().copied().zip(&configs).collect();
+        let (uri, config) = workspace[workspace.len() - 1];
+        let mut diagnostics = diagnose(uri, config, &workspace, None);
+        diagnostics.retain(|diagnostic| diagnostic.code == code);
+        diagnostics
+    }
+
+    /// Line and first related line of each diagnostic.
+    fn lines(diagnostics: &[Diagnostic]) -> Vec<(u32, Option<u32>)> {
+        diagnostics
+            .iter()
+            .map(|diagnostic| {
+                (diagnostic.range.start.line, diagnostic.related.first().map(|related| related.range.start.line))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn backends_used_as_tables_or_tracked_are_used() {
+        let text = "\
+frontend fe
+  bind :80
+  http-request track-sc0 src table st_src
+  http-request deny if { sc_http_req_rate(0,st_rate) gt 10 }
+  default_backend app
+backend st_src
+  server x 10.0.0.9:80
+backend st_rate
+  stick-table type ip size 1m store http_req_rate(10s)
+backend app
+  server s1 10.0.0.1:80 track health/h1
+backend health
+  server h1 10.0.0.3:80 check
+backend unused
+  server u 10.0.0.4:80
+";
+        assert_eq!(codes(text), [("unused-backend", 13)]);
+    }
+
+    #[test]
+    fn acls_resolve_in_their_section_and_its_defaults() {
+        let text = "\
+defaults
+  acl inherited src 10.0.0.0/8
+frontend a
+  bind :80
+  acl local path /a
+  http-request deny if local inherited !other
+frontend b
+  bind :81
+  acl other path /b
+  http-request deny if local other
+";
+        assert_eq!(codes(text), [("undefined-acl", 5), ("undefined-acl", 9)]);
+    }
+
+    #[test]
+    fn frontends_and_backends_may_share_a_name_but_not_with_a_listen() {
+        assert!(found("duplicate-proxy", &["frontend app\n  bind :80\nbackend app\n"]).is_empty());
+
+        let text = "frontend app\n  bind :80\nbackend app\nlisten app\n  bind :81\nbackend app\n";
+        let duplicates = found("duplicate-proxy", &[text]);
+        assert_eq!(lines(&duplicates), [(3, Some(0)), (5, Some(2))]);
+        assert!(duplicates.iter().all(|diagnostic| diagnostic.severity == Severity::Error));
+
+        // Across files, the later one reports the clash
+        assert!(found("duplicate-proxy", &["frontend app\n", "backend app\n"]).is_empty());
+        assert!(found("duplicate-proxy", &["backend app\nlisten app\n", "frontend web\n"]).is_empty());
+        let duplicates = found("duplicate-proxy", &["backend app\n", "listen app\n"]);
+        assert_eq!(lines(&duplicates), [(0, Some(0))]);
+        assert_eq!(duplicates[0].related[0].uri, "a.cfg");
+    }
+
+    #[test]
+    fn servers_are_duplicates_only_within_one_backend() {
+        let text = "\
+backend a
+  server s1 10.0.0.1:80
+  server s2 10.0.0.2:80
+  server s1 10.0.0.3:80
+backend b
+  server s1 10.0.0.1:80
+";
+        let duplicates = found("duplicate-server", &[text]);
+        assert_eq!(lines(&duplicates), [(3, Some(1))]);
+        assert_eq!(duplicates[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn acls_declared_again_in_a_section_are_pointed_out() {
+        let text = "\
+frontend a
+  acl api path_beg /api
+  acl api path_beg /v2
+  http-request deny if api
+frontend b
+  acl api path_beg /api
+  http-request deny if api
+";
+        let duplicates = found("duplicate-acl", &[text]);
+        assert_eq!(lines(&duplicates), [(2, Some(1))]);
+        assert_eq!(duplicates[0].severity, Severity::Information);
+    }
+
+    #[test]
+    fn acls_no_condition_uses_are_unnecessary() {
+        let text = "\
+frontend a
+  acl api path_beg /api
+  acl api path_beg /v2
+  acl idle path /idle
+  http-request deny if api
+";
+        let unused = found("unused-acl", &[text]);
+        assert_eq!(lines(&unused), [(3, None)]);
+        assert_eq!(unused[0].severity, Severity::Warning);
+        assert_eq!(unused[0].tags, [DiagnosticTag::Unnecessary]);
+
+        assert!(found("unused-acl", &["defaults\n  acl api path_beg /api\nfrontend a\n  http-request deny if api\n"])
+            .is_empty());
+    }
+
+    #[test]
+    fn backends_nothing_routes_to_are_unnecessary_unless_they_may_be_read() {
+        let text = "frontend a\n  default_backend app\nbackend app\nbackend idle\n";
+        let unused = found("unused-backend", &[text]);
+        assert_eq!(lines(&unused), [(3, None)]);
+        assert_eq!((unused[0].severity, &unused[0].tags), (Severity::Warning, &vec![DiagnosticTag::Unnecessary]));
+
+        // A stick table may be read by sample fetches, and a dynamic target may pick any backend
+        assert!(found("unused-backend", &["backend rates\n  stick-table type ip size 1m\n"]).is_empty());
+        let dynamic = "frontend a\n  use_backend %[req.hdr(host),lower]\nbackend idle\n";
+        assert!(found("unused-backend", &[dynamic]).is_empty());
+        assert!(found("unused-backend", &[dynamic, "backend idle\n"]).is_empty());
+    }
+
+    #[test]
+    fn servers_with_weight_zero_need_a_use_server_rule_or_a_tracker() {
+        let text = "\
+backend app
+  use-server s1 if { path /s1 }
+  server s1 10.0.0.1:80 weight 0
+  server s2 10.0.0.2:80 weight 0
+  server s3 10.0.0.3:80 weight 0
+  server s4 10.0.0.4:80 track s3
+  server s5 10.0.0.5:80
+";
+        let unused = found("unused-server", &[text]);
+        assert_eq!(lines(&unused), [(3, None)]);
+        assert_eq!((unused[0].severity, &unused[0].tags), (Severity::Hint, &vec![DiagnosticTag::Unnecessary]));
+    }
+
+    #[test]
+    fn proxy_keywords_in_the_wrong_proxy_only_warn() {
+        let text = "\
+global
+  bind :80
+frontend a
+  bind :80
+  server s1 10.0.0.1:80
+  daemon
+backend b
+  bind :81
+  balance roundrobin
+";
+        let misplaced: Vec<(u32, Severity)> = found("misplaced-keyword", &[text])
+            .iter()
+            .map(|diagnostic| (diagnostic.range.start.line, diagnostic.severity))
+            .collect();
+        assert_eq!(
+            misplaced,
+            [(1, Severity::Error), (4, Severity::Warning), (5, Severity::Error), (7, Severity::Warning)]
+        );
+    }
+
+    #[test]
+    fn a_target_version_turns_what_it_lacks_into_errors() {
+        let text = "\
+frontend a
+  bind quic4@:443
+  reqadd X-Forwarded-Proto:\\ https
+  http-request return status 200
+crt-store web
+";
+        let targeting = |version: Option<&str>| -> Vec<(&str, u32, Severity)> {
+            let config = ConfigFile::parse(text);
+            let version = version.map(|version| version.parse().unwrap());
+            diagnose("haproxy.cfg", &config, &[("haproxy.cfg", &config)], version)
+                .iter()
+                .map(|diagnostic| (diagnostic.code, diagnostic.range.start.line, diagnostic.severity))
+                .collect()
+        };
+        let removed = ("deprecated-keyword", 2, Severity::Warning);
+        let unsupported = |line| ("unsupported-keyword", line, Severity::Error);
+        assert_eq!(targeting(None), [removed]);
+        assert_eq!(targeting(Some("3.0")), [unsupported(2)]);
+        assert_eq!(targeting(Some("2.4")), [unsupported(1), unsupported(2), unsupported(4)]);
+        assert_eq!(targeting(Some("2.0")), [unsupported(1), removed, unsupported(3), unsupported(4)]);
+    }
+
+    #[test]
+    fn address_prefixes_need_the_release_that_added_them() {
+        let config = ConfigFile::parse("frontend a\n  bind mptcp4@:80\nbackend b\n  server s rhttp@\n");
+        let targeting = |version: &str| -> Vec<(&str, u32)> {
+            diagnose("haproxy.cfg", &config, &[("haproxy.cfg", &config)], Some(version.parse().unwrap()))
+                .iter()
+                .filter(|diagnostic| diagnostic.code != "unused-backend")
+                .map(|diagnostic| (diagnostic.code, diagnostic.range.start.line))
+                .collect()
+        };
+        assert_eq!(targeting("3.1"), []);
+        assert_eq!(targeting("3.0"), [("unsupported-keyword", 1)]);
+        assert_eq!(targeting("2.8"), [("unsupported-keyword", 1), ("unsupported-keyword", 3)]);
+    }
+}
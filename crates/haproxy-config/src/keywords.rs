@@ -0,0 +1,659 @@
+//! Every directive keyword HAProxy knows, the sections it is legal in and the
+//! versions that introduced or removed it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::config::SectionKind::*;
+use crate::config::{Directive, SectionKind};
+use crate::position::Range;
+
+/// A HAProxy release line such as 2.8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl Version {
+    pub const fn new(major: u8, minor: u8) -> Self {
+        Version { major, minor }
+    }
+}
+
+/// Reads `2.8`, `2.8.3` or `3`; only the release line is kept.
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parts = text.trim().split('.');
+        let number = |part: Option<&str>| match part {
+            Some(part) => part.parse::<u8>().map_err(|_| format!("invalid HAProxy version: {}", text)),
+            None => Ok(0),
+        };
+        let major = number(parts.next())?;
+        let minor = number(parts.next())?;
+        number(parts.next())?;
+        if parts.next().is_some() {
+            return Err(format!("invalid HAProxy version: {}", text));
+        }
+        Ok(Version { major, minor })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// A set of section kinds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sections(u32);
+
+impl Sections {
+    pub const fn of(kinds: &[SectionKind]) -> Self {
+        let mut bits = 0;
+        let mut index = 0;
+        while index < kinds.len() {
+            bits |= 1 << kinds[index] as u32;
+            index += 1;
+        }
+        Sections(bits)
+    }
+
+    pub const fn with(self, other: Sections) -> Self {
+        Sections(self.0 | other.0)
+    }
+
+    pub fn contains(self, kind: SectionKind) -> bool {
+        self.0 & (1 << kind as u32) != 0
+    }
+
+    /// The kinds in the set, in `SectionKind::ALL` order.
+    pub fn kinds(self) -> impl Iterator<Item = SectionKind> {
+        SectionKind::ALL.into_iter().filter(move |kind| self.contains(*kind))
+    }
+}
+
+/// One entry of the keyword table.
+#[derive(Debug, Clone, Copy)]
+pub struct Keyword {
+    /// The keyword as written, with its first argument for keywords whose legality
+    /// depends on it (`option httpchk`, `timeout client`). A trailing `*` matches
+    /// any suffix, as for the `tune.` family.
+    pub name: &'static str,
+    pub sections: Sections,
+    /// First release that accepts the keyword.
+    pub since: Version,
+    /// First release that rejects it again.
+    pub removed: Option<Version>,
+    /// What to write instead once it is removed, e.g. `http-request add-header` for `reqadd`.
+    pub replaced_by: Option<&'static str>,
+}
+
+impl Keyword {
+    const fn new(name: &'static str, sections: Sections) -> Self {
+        Keyword { name, sections, since: OLDEST, removed: None, replaced_by: None }
+    }
+
+    const fn since(mut self, major: u8, minor: u8) -> Self {
+        self.since = Version::new(major, minor);
+        self
+    }
+
+    const fn removed(mut self, major: u8, minor: u8) -> Self {
+        self.removed = Some(Version::new(major, minor));
+        self
+    }
+
+    const fn replaced_by(mut self, replacement: &'static str) -> Self {
+        self.replaced_by = Some(replacement);
+        self
+    }
+
+    pub fn allowed_in(&self, kind: SectionKind) -> bool {
+        self.sections.contains(kind)
+    }
+
+    /// Whether `version` accepts the keyword at all.
+    pub fn available_in(&self, version: Version) -> bool {
+        self.since <= version && self.removed.is_none_or(|removed| version < removed)
+    }
+
+    /// Number of words `name` spans in a directive.
+    fn words(&self) -> usize {
+        self.name.split(' ').count()
+    }
+}
+
+/// Oldest release the table describes; keywords without a `since` predate it.
+pub const OLDEST: Version = Version::new(1, 8);
+
+const GLOBAL: Sections = Sections::of(&[Global]);
+const DFLB: Sections = Sections::of(&[Defaults, Frontend, Listen, Backend]);
+const DFL: Sections = Sections::of(&[Defaults, Frontend, Listen]);
+const DLB: Sections = Sections::of(&[Defaults, Listen, Backend]);
+const FLB: Sections = Sections::of(&[Frontend, Listen, Backend]);
+const FL: Sections = Sections::of(&[Frontend, Listen]);
+const LB: Sections = Sections::of(&[Listen, Backend]);
+const PEERS: Sections = Sections::of(&[Peers]);
+const RESOLVERS: Sections = Sections::of(&[Resolvers]);
+const USERLIST: Sections = Sections::of(&[Userlist]);
+const MAILERS: Sections = Sections::of(&[Mailers]);
+const CACHE: Sections = Sections::of(&[Cache]);
+const PROGRAM: Sections = Sections::of(&[Program]);
+const HTTP_ERRORS: Sections = Sections::of(&[HttpErrors]);
+const RING: Sections = Sections::of(&[Ring]);
+const LOG_FORWARD: Sections = Sections::of(&[LogForward]);
+const FCGI_APP: Sections = Sections::of(&[FcgiApp]);
+const CRT_STORE: Sections = Sections::of(&[CrtStore]);
+const TRACES: Sections = Sections::of(&[Traces]);
+
+const fn kw(name: &'static str, sections: Sections) -> Keyword {
+    Keyword::new(name, sections)
+}
+
+/// The keyword table, following the keyword lists of HAProxy's configuration manual.
+pub static KEYWORDS: &[Keyword] = &[
+    // global: process management, security and performance tuning
+    kw("51degrees-*", GLOBAL),
+    kw("anonkey", GLOBAL).since(2, 7),
+    kw("busy-polling", GLOBAL),
+    kw("ca-base", GLOBAL),
+    kw("chroot", GLOBAL),
+    kw("close-spread-time", GLOBAL).since(2, 6),
+    kw("cluster-secret", GLOBAL).since(2, 4),
+    kw("cpu-map", GLOBAL),
+    kw("crt-base", GLOBAL.with(CRT_STORE)),
+    kw("daemon", GLOBAL),
+    kw("debug", GLOBAL),
+    kw("default-path", GLOBAL).since(2, 4),
+    kw("deviceatlas-*", GLOBAL),
+    kw("expose-experimental-directives", GLOBAL).since(2, 5),
+    kw("external-check", GLOBAL),
+    kw("fd-hard-limit", GLOBAL).since(2, 6),
+    kw("gid", GLOBAL),
+    kw("group", GLOBAL.with(USERLIST).with(PROGRAM)),
+    kw("h1-accept-payload-with-any-method", GLOBAL).since(2, 6),
+    kw("h1-case-adjust", GLOBAL).since(2, 0),
+    kw("h1-case-adjust-file", GLOBAL).since(2, 0),
+    kw("h2-workaround-bogus-websocket-clients", GLOBAL).since(2, 4),
+    kw("hard-stop-after", GLOBAL),
+    kw("httpclient.*", GLOBAL).since(2, 5),
+    kw("insecure-fork-wanted", GLOBAL).since(2, 2),
+    kw("insecure-setuid-wanted", GLOBAL).since(2, 2),
+    kw("issuers-chain-path", GLOBAL).since(2, 2),
+    kw("key-base", GLOBAL.with(CRT_STORE)).since(3, 0),
+    kw("limited-quic", GLOBAL).since(2, 8),
+    kw("localpeer", GLOBAL).since(2, 0),
+    kw("log-send-hostname", GLOBAL),
+    kw("lua-load", GLOBAL),
+    kw("lua-load-per-thread", GLOBAL).since(2, 4),
+    kw("lua-prepend-path", GLOBAL).since(2, 1),
+    kw("master-worker", GLOBAL),
+    kw("max-spread-checks", GLOBAL),
+    kw("maxcompcpuusage", GLOBAL),
+    kw("maxcomprate", GLOBAL),
+    kw("maxconnrate", GLOBAL),
+    kw("maxpipes", GLOBAL),
+    kw("maxsessrate", GLOBAL),
+    kw("maxsslconn", GLOBAL),
+    kw("maxsslrate", GLOBAL),
+    kw("maxzlibmem", GLOBAL),
+    kw("mworker-max-reloads", GLOBAL).since(1, 9),
+    kw("nbproc", GLOBAL).removed(2, 5).replaced_by("nbthread"),
+    kw("nbthread", GLOBAL),
+    kw("no-memory-trimming", GLOBAL).since(2, 6),
+    kw("node", GLOBAL),
+    kw("noepoll", GLOBAL),
+    kw("noevports", GLOBAL),
+    kw("nogetaddrinfo", GLOBAL),
+    kw("nokqueue", GLOBAL),
+    kw("nopoll", GLOBAL),
+    kw("noreuseport", GLOBAL),
+    kw("nosplice", GLOBAL),
+    kw("numa-cpu-mapping", GLOBAL).since(2, 4),
+    kw("ocsp-update.*", GLOBAL).since(3, 0),
+    kw("pidfile", GLOBAL),
+    kw("pp2-never-send-local", GLOBAL).since(2, 4),
+    kw("prealloc-fd", GLOBAL).since(2, 7),
+    kw("presetenv", GLOBAL),
+    kw("profiling.*", GLOBAL).since(2, 0),
+    kw("quiet", GLOBAL),
+    kw("resetenv", GLOBAL),
+    kw("server-state-base", GLOBAL),
+    kw("server-state-file", GLOBAL),
+    kw("set-dumpable", GLOBAL).since(1, 9),
+    kw("set-var", GLOBAL).since(2, 4),
+    kw("set-var-fmt", GLOBAL).since(2, 6),
+    kw("setcap", GLOBAL).since(2, 9),
+    kw("setenv", GLOBAL),
+    kw("spread-checks", GLOBAL),
+    kw("ssl-*", GLOBAL),
+    kw("stats bind-process", GLOBAL).removed(2, 5),
+    kw("stats maxconn", GLOBAL),
+    kw("stats socket", GLOBAL),
+    kw("stats timeout", GLOBAL),
+    kw("strict-limits", GLOBAL).since(2, 0),
+    kw("thread-group", GLOBAL).since(2, 7),
+    kw("thread-groups", GLOBAL).since(2, 7),
+    kw("trace", GLOBAL.with(TRACES)).since(3, 1),
+    kw("tune.*", GLOBAL),
+    kw("uid", GLOBAL),
+    kw("ulimit-n", GLOBAL),
+    kw("unix-bind", GLOBAL),
+    kw("unsetenv", GLOBAL),
+    kw("user", GLOBAL.with(USERLIST).with(PROGRAM)),
+    kw("wurfl-*", GLOBAL),
+    kw("zero-warning", GLOBAL).since(2, 2),
+    // proxies: defaults, frontend, listen and backend
+    kw("acl", DFLB.with(FCGI_APP)),
+    kw("appsession", LB).removed(1, 6).replaced_by("stick on"),
+    kw("backlog", DFL.with(LOG_FORWARD)),
+    kw("balance", DLB),
+    kw("bind", FL.with(PEERS).with(LOG_FORWARD)),
+    kw("bind-process", DFLB).removed(2, 5),
+    kw("block", FLB).removed(2, 1).replaced_by("http-request deny"),
+    kw("capture", FL),
+    kw("clitcpka-cnt", DFL),
+    kw("clitcpka-idle", DFL),
+    kw("clitcpka-intvl", DFL),
+    kw("compression", DFLB),
+    kw("cookie", DLB),
+    kw("declare", FL),
+    kw("default-server", DLB.with(PEERS)),
+    kw("default_backend", DFL),
+    kw("description", GLOBAL.with(FLB).with(RING)),
+    kw("disabled", DFLB.with(PEERS)),
+    kw("dispatch", LB),
+    kw("dynamic-cookie-key", DLB),
+    kw("email-alert", DFLB),
+    kw("enabled", DFLB),
+    kw("error-log-format", DFL).since(3, 0),
+    kw("errorfile", DFLB.with(HTTP_ERRORS)),
+    kw("errorfiles", DFLB).since(2, 2),
+    kw("errorloc", DFLB),
+    kw("errorloc302", DFLB),
+    kw("errorloc303", DFLB),
+    kw("external-check command", DLB),
+    kw("external-check path", DLB),
+    kw("filter", FLB),
+    kw("force-persist", FLB),
+    kw("fullconn", DLB),
+    kw("guid", FLB).since(3, 0),
+    kw("hash-balance-factor", DLB),
+    kw("hash-key", DLB).since(3, 0),
+    kw("hash-type", DLB),
+    kw("http-after-response", DFLB).since(2, 2),
+    kw("http-check", DLB),
+    kw("http-error", DFLB).since(2, 2),
+    kw("http-request", DFLB),
+    kw("http-request add-acl", DFLB),
+    kw("http-request add-header", DFLB),
+    kw("http-request allow", DFLB),
+    kw("http-request auth", DFLB),
+    kw("http-request cache-use", DFLB),
+    kw("http-request capture", DFLB),
+    kw("http-request del-acl", DFLB),
+    kw("http-request del-header", DFLB),
+    kw("http-request del-map", DFLB),
+    kw("http-request deny", DFLB),
+    kw("http-request disable-l7-retry", DFLB).since(2, 0),
+    kw("http-request do-resolve", DFLB).since(2, 0),
+    kw("http-request early-hint", DFLB).since(1, 9),
+    kw("http-request normalize-uri", DFLB).since(2, 4),
+    kw("http-request redirect", DFLB),
+    kw("http-request reject", DFLB),
+    kw("http-request replace-header", DFLB),
+    kw("http-request replace-path", DFLB).since(2, 1),
+    kw("http-request replace-pathq", DFLB).since(2, 2),
+    kw("http-request replace-uri", DFLB),
+    kw("http-request replace-value", DFLB),
+    kw("http-request return", DFLB).since(2, 2),
+    kw("http-request sc-inc-gpc0", DFLB),
+    kw("http-request sc-inc-gpc1", DFLB),
+    kw("http-request sc-set-gpt0", DFLB),
+    kw("http-request send-spoe-group", DFLB),
+    kw("http-request set-bandwidth-limit", DFLB).since(2, 7),
+    kw("http-request set-dst", DFLB),
+    kw("http-request set-dst-port", DFLB),
+    kw("http-request set-header", DFLB),
+    kw("http-request set-log-level", DFLB),
+    kw("http-request set-map", DFLB),
+    kw("http-request set-mark", DFLB),
+    kw("http-request set-method", DFLB),
+    kw("http-request set-nice", DFLB),
+    kw("http-request set-path", DFLB),
+    kw("http-request set-pathq", DFLB).since(2, 2),
+    kw("http-request set-priority-class", DFLB),
+    kw("http-request set-priority-offset", DFLB),
+    kw("http-request set-query", DFLB),
+    kw("http-request set-src", DFLB),
+    kw("http-request set-src-port", DFLB),
+    kw("http-request set-timeout", DFLB).since(2, 4),
+    kw("http-request set-tos", DFLB),
+    kw("http-request set-uri", DFLB),
+    kw("http-request set-var", DFLB),
+    kw("http-request set-var-fmt", DFLB).since(2, 6),
+    kw("http-request silent-drop", DFLB),
+    kw("http-request strict-mode", DFLB).since(2, 2),
+    kw("http-request tarpit", DFLB),
+    kw("http-request track-sc0", DFLB),
+    kw("http-request track-sc1", DFLB),
+    kw("http-request track-sc2", DFLB),
+    kw("http-request unset-var", DFLB),
+    kw("http-request use-service", DFLB),
+    kw("http-request wait-for-body", DFLB).since(2, 4),
+    kw("http-response", DFLB),
+    kw("http-response add-acl", DFLB),
+    kw("http-response add-header", DFLB),
+    kw("http-response allow", DFLB),
+    kw("http-response cache-store", DFLB),
+    kw("http-response capture", DFLB),
+    kw("http-response del-acl", DFLB),
+    kw("http-response del-header", DFLB),
+    kw("http-response del-map", DFLB),
+    kw("http-response deny", DFLB),
+    kw("http-response redirect", DFLB),
+    kw("http-response replace-header", DFLB),
+    kw("http-response replace-value", DFLB),
+    kw("http-response return", DFLB).since(2, 2),
+    kw("http-response sc-inc-gpc0", DFLB),
+    kw("http-response sc-inc-gpc1", DFLB),
+    kw("http-response sc-set-gpt0", DFLB),
+    kw("http-response send-spoe-group", DFLB),
+    kw("http-response set-bandwidth-limit", DFLB).since(2, 7),
+    kw("http-response set-header", DFLB),
+    kw("http-response set-log-level", DFLB),
+    kw("http-response set-map", DFLB),
+    kw("http-response set-mark", DFLB),
+    kw("http-response set-nice", DFLB),
+    kw("http-response set-status", DFLB),
+    kw("http-response set-tos", DFLB),
+    kw("http-response set-var", DFLB),
+    kw("http-response silent-drop", DFLB),
+    kw("http-response strict-mode", DFLB).since(2, 2),
+    kw("http-response track-sc0", DFLB),
+    kw("http-response track-sc1", DFLB),
+    kw("http-response track-sc2", DFLB),
+    kw("http-response unset-var", DFLB),
+    kw("http-response wait-for-body", DFLB).since(2, 4),
+    kw("http-reuse", DLB),
+    kw("http-send-name-header", LB),
+    kw("id", FLB),
+    kw("ignore-persist", FLB),
+    kw("load-server-state-from-file", DLB),
+    kw("log", GLOBAL.with(DFLB).with(PEERS).with(LOG_FORWARD)),
+    kw("log-format", DFL),
+    kw("log-format-sd", DFL),
+    kw("log-steps", DFLB).since(3, 1),
+    kw("log-tag", GLOBAL.with(DFLB)),
+    kw("max-keep-alive-queue", DLB),
+    kw("max-session-srv-conns", DFL).since(1, 9),
+    kw("maxconn", GLOBAL.with(DFL).with(LOG_FORWARD)),
+    kw("mode", DFLB),
+    kw("monitor", FL),
+    kw("monitor-net", DFL).removed(2, 4).replaced_by("http-request return status 200 if { src <network> }"),
+    kw("monitor-uri", DFL),
+    kw("persist", DLB),
+    kw("quic-initial", DFL).since(3, 1),
+    kw("rate-limit", DFL),
+    kw("redirect", FLB),
+    kw("redispatch", DLB).removed(1, 5).replaced_by("option redispatch"),
+    kw("reqadd", FLB).removed(2, 1).replaced_by("http-request add-header"),
+    kw("reqallow", FLB).removed(2, 1).replaced_by("http-request allow"),
+    kw("reqdel", FLB).removed(2, 1).replaced_by("http-request del-header"),
+    kw("reqdeny", FLB).removed(2, 1).replaced_by("http-request deny"),
+    kw("reqiallow", FLB).removed(2, 1).replaced_by("http-request allow"),
+    kw("reqidel", FLB).removed(2, 1).replaced_by("http-request del-header"),
+    kw("reqideny", FLB).removed(2, 1).replaced_by("http-request deny"),
+    kw("reqipass", FLB).removed(2, 1).replaced_by("http-request allow"),
+    kw("reqirep", FLB).removed(2, 1).replaced_by("http-request replace-header"),
+    kw("reqitarpit", FLB).removed(2, 1).replaced_by("http-request tarpit"),
+    kw("reqpass", FLB).removed(2, 1).replaced_by("http-request allow"),
+    kw("reqrep", FLB).removed(2, 1).replaced_by("http-request replace-header"),
+    kw("reqtarpit", FLB).removed(2, 1).replaced_by("http-request tarpit"),
+    kw("retries", DLB),
+    kw("retry-on", DLB).since(2, 0),
+    kw("rspadd", FLB).removed(2, 1).replaced_by("http-response add-header"),
+    kw("rspdel", FLB).removed(2, 1).replaced_by("http-response del-header"),
+    kw("rspdeny", FLB).removed(2, 1).replaced_by("http-response deny"),
+    kw("rspidel", FLB).removed(2, 1).replaced_by("http-response del-header"),
+    kw("rspideny", FLB).removed(2, 1).replaced_by("http-response deny"),
+    kw("rspirep", FLB).removed(2, 1).replaced_by("http-response replace-header"),
+    kw("rsprep", FLB).removed(2, 1).replaced_by("http-response replace-header"),
+    kw("server", LB.with(PEERS).with(RING)),
+    kw("server-state-file-name", DLB),
+    kw("server-template", LB),
+    kw("source", DLB),
+    kw("srvtcpka-cnt", DLB),
+    kw("srvtcpka-idle", DLB),
+    kw("srvtcpka-intvl", DLB),
+    // Not the global `ssl-*` tuning keywords
+    kw("ssl-f-use", FL).since(3, 2),
+    kw("stats", DFLB),
+    kw("stick", LB),
+    kw("stick-table", FLB),
+    kw("tcp-check", DLB),
+    kw("tcp-request", DFLB),
+    kw("tcp-request connection", DFL),
+    kw("tcp-request session", DFL),
+    kw("tcp-response", DLB),
+    kw("timeout", DFLB.with(RESOLVERS).with(MAILERS).with(RING).with(LOG_FORWARD)),
+    kw("timeout check", DLB),
+    kw("timeout client", DFL.with(LOG_FORWARD)),
+    kw("timeout client-fin", DFL),
+    kw("timeout client-hs", DFL).since(2, 8),
+    kw("timeout connect", DLB.with(RING)),
+    kw("timeout http-keep-alive", DFLB),
+    kw("timeout http-request", DFLB),
+    kw("timeout mail", MAILERS),
+    kw("timeout queue", DLB),
+    kw("timeout resolve", RESOLVERS),
+    kw("timeout retry", RESOLVERS),
+    kw("timeout server", DLB.with(RING)),
+    kw("timeout server-fin", DLB),
+    kw("timeout tarpit", DFLB),
+    kw("timeout tunnel", DLB),
+    kw("transparent", DLB),
+    kw("unique-id-format", DFL),
+    kw("unique-id-header", DFL),
+    kw("use-fcgi-app", LB).since(2, 1),
+    kw("use-server", LB),
+    kw("use_backend", FL),
+    // proxy options
+    kw("option", DFLB.with(PROGRAM).with(LOG_FORWARD).with(FCGI_APP)),
+    kw("option abortonclose", DLB),
+    kw("option accept-invalid-http-request", DFL),
+    kw("option accept-invalid-http-response", DLB),
+    kw("option accept-unsafe-violations-in-http-request", DFL).since(3, 0),
+    kw("option accept-unsafe-violations-in-http-response", DLB).since(3, 0),
+    kw("option allbackups", DLB),
+    kw("option checkcache", DLB),
+    kw("option clitcpka", DFL),
+    kw("option contstats", DFL),
+    kw("option disable-h2-upgrade", DFL).since(2, 0),
+    kw("option dontlog-normal", DFL),
+    kw("option dontlognull", DFL),
+    kw("option external-check", DLB),
+    kw("option forwarded", DLB).since(2, 8),
+    kw("option forwardfor", DFLB),
+    kw("option h1-case-adjust-bogus-client", DFL).since(2, 0),
+    kw("option h1-case-adjust-bogus-server", DLB).since(2, 0),
+    kw("option http-buffer-request", DFLB),
+    kw("option http-ignore-probes", DFL),
+    kw("option http-keep-alive", DFLB),
+    kw("option http-no-delay", DFLB),
+    kw("option http-pretend-keepalive", DLB),
+    kw("option http-restrict-req-hdr-names", DFLB).since(2, 6),
+    kw("option http-server-close", DFLB),
+    kw("option http-tunnel", DFLB).removed(2, 1),
+    kw("option http-use-proxy-header", DFL),
+    kw("option http_proxy", DFLB).removed(2, 5),
+    kw("option httpchk", DLB),
+    kw("option httpclose", DFLB),
+    kw("option httplog", DFL),
+    kw("option httpslog", DFL).since(2, 5),
+    kw("option idle-close-on-response", DFL).since(2, 6),
+    kw("option independant-streams", DFLB).removed(1, 5).replaced_by("option independent-streams"),
+    kw("option independent-streams", DFLB),
+    kw("option ldap-check", DLB),
+    kw("option log-health-checks", DLB),
+    kw("option log-separate-errors", DFL),
+    kw("option logasap", DFL),
+    kw("option mysql-check", DLB),
+    kw("option nolinger", DFLB),
+    kw("option originalto", DFLB),
+    kw("option persist", DLB),
+    kw("option pgsql-check", DLB),
+    kw("option prefer-last-server", DLB),
+    kw("option redis-check", DLB),
+    kw("option redispatch", DLB),
+    kw("option smtpchk", DLB),
+    kw("option socket-stats", DFL),
+    kw("option splice-auto", DFLB),
+    kw("option splice-request", DFLB),
+    kw("option splice-response", DFLB),
+    kw("option spop-check", DLB),
+    kw("option srvtcpka", DLB),
+    kw("option ssl-hello-chk", DLB),
+    kw("option tcp-check", DLB),
+    kw("option tcp-smart-accept", DFL),
+    kw("option tcp-smart-connect", DLB),
+    kw("option tcpka", DFLB),
+    kw("option tcplog", DFL),
+    kw("option transparent", DLB),
+    kw("option start-on-reload", PROGRAM),
+    kw("option assume-rfc6587-ntf", LOG_FORWARD).since(3, 1),
+    kw("option dont-parse-log", LOG_FORWARD).since(3, 1),
+    kw("option get-values", FCGI_APP),
+    kw("option keep-conn", FCGI_APP),
+    kw("option max-reqs", FCGI_APP),
+    kw("option mpxs-conns", FCGI_APP),
+    // peers
+    kw("peer", PEERS),
+    kw("shards", PEERS).since(2, 9),
+    kw("table", PEERS).since(2, 0),
+    // resolvers
+    kw("accepted_payload_size", RESOLVERS),
+    kw("hold", RESOLVERS),
+    kw("nameserver", RESOLVERS),
+    kw("parse-resolv-conf", RESOLVERS).since(2, 1),
+    kw("resolve_retries", RESOLVERS),
+    // mailers
+    kw("mailer", MAILERS),
+    // cache
+    kw("max-age", CACHE),
+    kw("max-object-size", CACHE).since(1, 9),
+    kw("max-secondary-entries", CACHE).since(2, 4),
+    kw("process-vary", CACHE).since(2, 4),
+    kw("total-max-size", CACHE),
+    // program
+    kw("command", PROGRAM).since(1, 9),
+    // ring
+    kw("backing-file", RING).since(2, 7),
+    kw("format", RING),
+    kw("maxlen", RING),
+    kw("size", RING),
+    // log-forward
+    kw("dgram-bind", LOG_FORWARD),
+    // fcgi-app
+    kw("docroot", FCGI_APP),
+    kw("index", FCGI_APP),
+    kw("log-stderr", FCGI_APP),
+    kw("pass-header", FCGI_APP),
+    kw("path-info", FCGI_APP),
+    kw("set-param", FCGI_APP),
+    // crt-store
+    kw("load", CRT_STORE).since(3, 0),
+];
+
+/// First release that accepts sections of `kind`.
+pub fn section_since(kind: SectionKind) -> Version {
+    match kind {
+        Program => Version::new(1, 9),
+        FcgiApp => Version::new(2, 1),
+        HttpErrors | Ring => Version::new(2, 2),
+        LogForward => Version::new(2, 3),
+        CrtStore => Version::new(3, 0),
+        Traces => Version::new(3, 1),
+        _ => OLDEST,
+    }
+}
+
+/// The release a file targets through a `# haproxy-lsp: version=2.8` comment.
+pub fn version_pragma(text: &str) -> Option<Version> {
+    text.lines()
+        .filter_map(|line| line.trim_start().strip_prefix('#')?.trim_start().strip_prefix("haproxy-lsp:"))
+        .flat_map(str::split_whitespace)
+        .find_map(|setting| setting.strip_prefix("version=")?.parse().ok())
+}
+
+/// Looks `name` up in [`KEYWORDS`], exactly or through a `*` family entry.
+pub fn keyword(name: &str) -> Option<&'static Keyword> {
+    static INDEX: OnceLock<HashMap<&'static str, &'static Keyword>> = OnceLock::new();
+    let index = INDEX.get_or_init(|| KEYWORDS.iter().map(|keyword| (keyword.name, keyword)).collect());
+
+    index.get(name).copied().or_else(|| {
+        KEYWORDS.iter().find(|keyword| {
+            keyword.name.strip_suffix('*').is_some_and(|prefix| !name.contains(' ') && name.starts_with(prefix))
+        })
+    })
+}
+
+impl Directive {
+    /// The table entry this directive matches, preferring the two-word form
+    /// (`option httpchk`) over the bare keyword, and the range of the words it
+    /// covers. A leading `no` (`no option httplog`) is skipped.
+    pub fn keyword_entry(&self) -> Option<(&'static Keyword, Range)> {
+        let mut words: Vec<_> = std::iter::once(&self.keyword).chain(&self.args).take(3).collect();
+        if words[0].text == "no" && words.len() > 1 {
+            words.remove(0);
+        }
+
+        let entry = words
+            .get(1)
+            .and_then(|arg| keyword(&format!("{} {}", words[0].text, arg.text)))
+            .or_else(|| keyword(&words[0].text))?;
+        let last = words[entry.words() - 1];
+        Some((entry, Range { start: words[0].range.start.clone(), end: last.range.end.clone() }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigFile;
+
+    /// The name and sections of the entry the first directive of `section` matches.
+    fn entry(section: &str, line: &str) -> Option<(&'static str, Vec<SectionKind>)> {
+        let config = ConfigFile::parse(&format!("{}\n  {}\n", section, line));
+        let (keyword, _) = config.sections[0].directives[0].keyword_entry()?;
+        Some((keyword.name, keyword.sections.kinds().collect()))
+    }
+
+    #[test]
+    fn proxy_subcommands_of_global_keywords() {
+        let proxies = vec![Defaults, Backend, Listen];
+        assert_eq!(
+            entry("backend b", "external-check command /bin/true"),
+            Some(("external-check command", proxies.clone()))
+        );
+        assert_eq!(entry("defaults", "external-check path /usr/bin"), Some(("external-check path", proxies)));
+        assert_eq!(entry("global", "external-check"), Some(("external-check", vec![Global])));
+        assert_eq!(entry("frontend f", "ssl-f-use crt a.pem"), Some(("ssl-f-use", vec![Frontend, Listen])));
+        assert_eq!(entry("global", "ssl-default-bind-ciphers x"), Some(("ssl-*", vec![Global])));
+    }
+
+    #[test]
+    fn two_word_entries_win_and_no_is_skipped() {
+        assert_eq!(entry("global", "stats socket /run/haproxy.sock").map(|(name, _)| name), Some("stats socket"));
+        assert_eq!(entry("backend b", "stats enable").map(|(name, _)| name), Some("stats"));
+        assert_eq!(entry("backend b", "no option httpchk").map(|(name, _)| name), Some("option httpchk"));
+        assert_eq!(entry("global", "tune.bufsize 16384").map(|(name, _)| name), Some("tune.*"));
+        assert_eq!(entry("global", "not-a-keyword"), None);
+    }
+}
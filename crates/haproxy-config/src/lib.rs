@@ -0,0 +1,46 @@
+//! Parser and analyzer for HAProxy configuration files.
+//!
+//! This is the engine behind `haproxy-lsp`, usable without speaking LSP: parse a
+//! file into a [`ConfigFile`], then look up the sections, directives and symbols
+//! in it, or [`diagnose`] it.
+//!
+//! ```
+//! use haproxy_config::{collect_symbols, ConfigFile, SymbolKind};
+//!
+//! let text = "frontend www\n  default_backend app\n\nbackend app\n  server s1 10.0.0.1:80\n";
+//! let config = ConfigFile::parse(text);
+//! let symbols = collect_symbols("haproxy.cfg", &config);
+//!
+//! let app = symbols.iter().find(|symbol| symbol.kind == SymbolKind::Backend).unwrap();
+//! assert_eq!(app.name, "app");
+//! assert_eq!(app.references.len(), 1);
+//! ```
+
+mod addresses;
+mod config;
+mod diagnostics;
+mod keywords;
+mod migrations;
+mod position;
+mod symbols;
+mod tokenizer;
+mod values;
+
+pub use addresses::{
+    address_prefix, parse_address, Address, AddressFamily, AddressPrefix, AddressRole, Ports, ADDRESS_PREFIXES,
+};
+pub use config::{ConfigFile, Directive, Edit, Reparsed, Section, SectionKind};
+pub use diagnostics::{
+    backend_uses, backend_uses_with, diagnose, diagnose_with, Diagnostic, DiagnosticTag, RelatedInformation, Severity,
+};
+pub use keywords::{
+    keyword, section_since, version_pragma, Keyword, Sections, Version, KEYWORDS, OLDEST,
+};
+pub use migrations::migrate;
+pub use position::{line_start, line_text, offset_at, LineIndex, Position, PositionEncoding, Range};
+pub use symbols::{
+    collect_symbols, collect_symbols_with, resolve_symbol, symbol_token_at, symbol_token_at_with, Reference,
+    ReferenceContext, Symbol, SymbolIndex, SymbolKind, TokenRole, PREDEFINED_ACLS,
+};
+pub use tokenizer::{tokenize, Token};
+pub use values::{parse_size, parse_time, ValueKind, BIND_OPTIONS, DIRECTIVE_VALUES, SERVER_OPTIONS};
@@ -0,0 +1,135 @@
+//! Mechanical rewrites of removed keywords into their modern equivalents.
+
+use crate::config::Directive;
+
+/// The text to replace `directive` with, or `None` if it isn't a removed keyword or
+/// has no equivalent that means exactly the same. `text` is the document the
+/// directive was parsed from, so conditions are carried over as written.
+pub fn migrate(directive: &Directive, text: &str) -> Option<String> {
+    // Arguments before the condition, and the condition as written
+    let (args, condition) = match directive.condition() {
+        Some((keyword, _)) => {
+            let index = directive.args.iter().position(|arg| arg.span == keyword.span)?;
+            let end = directive.args.last()?.span.end;
+            (&directive.args[..index], Some(text.get(keyword.span.start..end)?))
+        }
+        None => (&directive.args[..], None),
+    };
+    let args: Vec<&str> = args.iter().map(|arg| arg.text.as_str()).collect();
+
+    let keyword = directive.keyword.text.as_str();
+    let (prefix, rule) = match keyword.get(..3) {
+        Some("req") => ("http-request", &keyword[3..]),
+        Some("rsp") => ("http-response", &keyword[3..]),
+        _ => ("", keyword),
+    };
+
+    let rewritten = match (prefix, rule, args.as_slice()) {
+        ("http-request" | "http-response", "add", [header]) => {
+            let (name, value) = header.split_once(':')?;
+            // `reqadd` values are literal, the new ones are log-format strings
+            let value = value.trim_start().replace('%', "%%");
+            format!("{} add-header {} {}", prefix, quote(name.trim()), quote(&value))
+        }
+        ("http-request" | "http-response", "del" | "idel", [regex]) => {
+            let (name, rest) = header_regex(regex)?;
+            if !matches!(rest, "" | ".*" | ".*$") {
+                return None;
+            }
+            format!("{} del-header {}", prefix, name)
+        }
+        ("http-request" | "http-response", "rep" | "irep", [search, replace]) => {
+            let (name, search) = header_regex(search)?;
+            let (replace_name, replace) = replace.split_once(':')?;
+            let replace = replace.trim_start_matches(' ');
+            if search.is_empty() || !name.eq_ignore_ascii_case(replace_name) {
+                return None;
+            }
+            // The old rules matched the whole line, the new one only the value
+            let flags = if rule == "irep" { "(?i)" } else { "" };
+            format!(
+                "{} replace-header {} {} {}",
+                prefix,
+                name,
+                quote(&format!("{}^{}", flags, search)),
+                quote(&replace.replace('%', "%%"))
+            )
+        }
+        ("", "block", []) => "http-request deny".to_string(),
+        ("", "redispatch", []) => "option redispatch".to_string(),
+        ("", "option", ["independant-streams"]) => "option independent-streams".to_string(),
+        ("", "no", ["option", "independant-streams"]) => "no option independent-streams".to_string(),
+        _ => return None,
+    };
+
+    Some(match condition {
+        Some(condition) => format!("{} {}", rewritten, condition),
+        None => rewritten,
+    })
+}
+
+/// Splits a `^Name: value` regex of the old header rules into the header name and
+/// the part matching the value, spaces after the colon dropped.
+fn header_regex(regex: &str) -> Option<(&str, &str)> {
+    let (name, rest) = regex.strip_prefix('^')?.split_once(':')?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return None;
+    }
+    let rest = rest.trim_start_matches(' ');
+    let rest = rest.strip_prefix(['*', '+', '?']).filter(|_| regex.contains(": ")).unwrap_or(rest);
+    Some((name, rest))
+}
+
+/// `value` as a config argument: bare if nothing in it needs quoting, otherwise in
+/// single quotes, which keep backslashes as they are.
+fn quote(value: &str) -> String {
+    let special = |c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | '#' | '$');
+    if !value.is_empty() && !value.contains(special) {
+        value.to_string()
+    } else if !value.contains('\'') {
+        format!("'{}'", value)
+    } else {
+        let escaped = value.replace('\\', "\\\\").replace('"', "\\\"").replace('$', "\\$");
+        format!("\"{}\"", escaped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigFile;
+
+    #[test]
+    fn removed_keywords_are_rewritten_with_their_conditions() {
+        let cases = [
+            (r"reqadd X-Proto:\ https", Some("http-request add-header X-Proto https")),
+            (r"rspadd X-Served-By:\ 100%\ ok", Some("http-response add-header X-Served-By '100%% ok'")),
+            (r"reqadd X-Note:\ it\'s", Some(r#"http-request add-header X-Note "it's""#)),
+            (r"reqadd X-Proto:\ https if { ssl_fc }", Some("http-request add-header X-Proto https if { ssl_fc }")),
+            ("reqdel ^X-Forwarded-For:.*", Some("http-request del-header X-Forwarded-For")),
+            ("reqidel ^Server:", Some("http-request del-header Server")),
+            ("rspdel ^Server:\\ .*$ unless local", Some("http-response del-header Server unless local")),
+            ("reqdel ^X-Debug:\\ on", None),
+            (
+                "reqirep ^Host:\\ (.*) Host:\\ \\1.internal",
+                Some(r"http-request replace-header Host (?i)^(.*) '\1.internal'"),
+            ),
+            ("reqrep ^Host:\\ www.(.*) Host:\\ \\1", Some(r"http-request replace-header Host ^www.(.*) '\1'")),
+            ("reqrep ^Host:\\ (.*) X-Host:\\ \\1", None),
+            ("block if { src 10.0.0.1 }", Some("http-request deny if { src 10.0.0.1 }")),
+            ("block unless allowed", Some("http-request deny unless allowed")),
+            ("redispatch", Some("option redispatch")),
+            ("option independant-streams", Some("option independent-streams")),
+            ("no option independant-streams", Some("no option independent-streams")),
+            // `stick on` needs a table, so there is nothing to rewrite to
+            ("appsession JSESSIONID len 52 timeout 3h", None),
+            ("http-request deny", None),
+        ];
+        for (line, expected) in cases {
+            let text = format!("backend b\n  {}\n", line);
+            let config = ConfigFile::parse(&text);
+            let directive = &config.sections[0].directives[0];
+            assert_eq!(migrate(directive, &text).as_deref(), expected, "{}", line);
+        }
+    }
+}
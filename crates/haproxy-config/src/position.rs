@@ -0,0 +1,244 @@
+//! Positions in configuration text and conversions between encodings.
+
+use crate::config::Edit;
+
+/// A span of text between two positions, end exclusive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// A zero-based line and byte column, the way the model records locations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+/// Byte offset of the start of the line containing `offset`.
+pub fn line_start(text: &str, offset: usize) -> usize {
+    text[..offset].rfind('\n').map(|newline| newline + 1).unwrap_or(0)
+}
+
+/// Byte offset of a position in `text`, clamped to the line and to the text.
+pub fn offset_at(text: &str, position: &Position) -> usize {
+    let mut start = 0;
+    for _ in 0..position.line {
+        match text[start..].find('\n') {
+            Some(newline) => start += newline + 1,
+            None => return text.len(),
+        }
+    }
+
+    let line_end = text[start..].find('\n').map(|newline| start + newline).unwrap_or(text.len());
+    let mut offset = (start + position.character as usize).min(line_end);
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+/// The text of line `line` without its terminator, or "" past the end of `text`.
+pub fn line_text(text: &str, line: u32) -> &str {
+    text.split('\n').nth(line as usize).unwrap_or("")
+}
+
+/// Where every line of a text starts, so lines can be looked up without scanning.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(newline, _)| newline + 1))
+            .collect();
+        LineIndex { starts }
+    }
+
+    /// Line `line` of `text`, the text this index was built from, like [`line_text`].
+    pub fn line<'a>(&self, text: &'a str, line: u32) -> &'a str {
+        let start = match self.starts.get(line as usize) {
+            Some(&start) => start,
+            None => return "",
+        };
+        let end = self.starts.get(line as usize + 1).map(|&next| next - 1).unwrap_or(text.len());
+        &text[start..end]
+    }
+
+    /// Byte offset of a position in `text`, clamped like [`offset_at`].
+    pub fn offset(&self, text: &str, position: &Position) -> usize {
+        let start = match self.starts.get(position.line as usize) {
+            Some(&start) => start,
+            None => return text.len(),
+        };
+        let line = self.line(text, position.line);
+        let mut offset = start + (position.character as usize).min(line.len());
+        while !text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+
+    /// The line and byte column of `offset`.
+    pub fn position(&self, offset: usize) -> Position {
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        Position { line: line as u32, character: (offset - self.starts[line]) as u32 }
+    }
+
+    /// Updates the index for `edit`, which turned the indexed text into `text`.
+    pub fn edit(&mut self, text: &str, edit: &Edit) {
+        let byte_delta = edit.new_end_byte as i64 - edit.old_end_byte as i64;
+        let removed = edit.start_line as usize + 1..edit.old_end_line as usize + 1;
+        for start in &mut self.starts[removed.end..] {
+            *start = (*start as i64 + byte_delta) as usize;
+        }
+        let inserted = text[edit.start_byte..edit.new_end_byte]
+            .match_indices('\n')
+            .map(|(newline, _)| edit.start_byte + newline + 1);
+        self.starts.splice(removed, inserted);
+    }
+}
+
+/// How the `character` of a position counts, e.g. as agreed with an LSP client.
+/// The model itself always works in byte columns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    /// The LSP default when the client doesn't offer anything else
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// The encoding an LSP `PositionEncodingKind` names.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "utf-8" => Some(PositionEncoding::Utf8),
+            "utf-16" => Some(PositionEncoding::Utf16),
+            "utf-32" => Some(PositionEncoding::Utf32),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PositionEncoding::Utf8 => "utf-8",
+            PositionEncoding::Utf16 => "utf-16",
+            PositionEncoding::Utf32 => "utf-32",
+        }
+    }
+
+    fn units(&self, c: char) -> usize {
+        match self {
+            PositionEncoding::Utf8 => c.len_utf8(),
+            PositionEncoding::Utf16 => c.len_utf16(),
+            PositionEncoding::Utf32 => 1,
+        }
+    }
+
+    /// Byte column within `line` of a column counted in this encoding. Columns past
+    /// the end clamp to it and columns inside a character snap to its start.
+    pub fn byte_column(&self, line: &str, character: u32) -> u32 {
+        let mut units = 0;
+        for (offset, c) in line.char_indices() {
+            units += self.units(c);
+            if units > character as usize {
+                return offset as u32;
+            }
+        }
+        line.len() as u32
+    }
+
+    /// Column counted in this encoding of a byte column within `line`.
+    pub fn column(&self, line: &str, byte_column: u32) -> u32 {
+        let mut end = (byte_column as usize).min(line.len());
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        line[..end].chars().map(|c| self.units(c)).sum::<usize>() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 'é' is 2 bytes and 1 UTF-16 unit, '😀' 4 bytes and 2 UTF-16 units
+    const LINE: &str = "aé😀b";
+
+    #[test]
+    fn columns_convert_between_encodings() {
+        let cases = [
+            (PositionEncoding::Utf8, [0, 1, 3, 7, 8]),
+            (PositionEncoding::Utf16, [0, 1, 2, 4, 5]),
+            (PositionEncoding::Utf32, [0, 1, 2, 3, 4]),
+        ];
+        let bytes = [0, 1, 3, 7, 8];
+        for (encoding, columns) in cases {
+            for (byte, column) in bytes.into_iter().zip(columns) {
+                assert_eq!(encoding.column(LINE, byte), column, "{:?} column of byte {}", encoding, byte);
+                assert_eq!(encoding.byte_column(LINE, column), byte, "{:?} byte of column {}", encoding, column);
+            }
+        }
+    }
+
+    #[test]
+    fn columns_inside_a_character_snap_to_its_start() {
+        // Between the two UTF-16 units of '😀'
+        assert_eq!(PositionEncoding::Utf16.byte_column(LINE, 3), 3);
+        // Inside the UTF-8 bytes of 'é' and '😀'
+        assert_eq!(PositionEncoding::Utf8.byte_column(LINE, 2), 1);
+        assert_eq!(PositionEncoding::Utf8.byte_column(LINE, 5), 3);
+        assert_eq!(PositionEncoding::Utf16.column(LINE, 5), 2);
+        assert_eq!(PositionEncoding::Utf32.column(LINE, 2), 1);
+    }
+
+    #[test]
+    fn columns_past_the_end_clamp() {
+        for encoding in [PositionEncoding::Utf8, PositionEncoding::Utf16, PositionEncoding::Utf32] {
+            assert_eq!(encoding.byte_column(LINE, 100), 8);
+            assert_eq!(encoding.column(LINE, 100), encoding.column(LINE, 8));
+        }
+    }
+
+    #[test]
+    fn offsets_clamp_to_the_line_and_text() {
+        let text = "ab\né😀\nlast";
+        assert_eq!(offset_at(text, &Position { line: 0, character: 9 }), 2);
+        assert_eq!(offset_at(text, &Position { line: 1, character: 1 }), 3);
+        assert_eq!(offset_at(text, &Position { line: 1, character: 3 }), 5);
+        assert_eq!(offset_at(text, &Position { line: 9, character: 0 }), text.len());
+        assert_eq!(line_start(text, 9), 3);
+    }
+
+    #[test]
+    fn line_index_agrees_with_line_text() {
+        for text in ["", "one", "one\ntwo\n", "\n\nthree\r\n"] {
+            let index = LineIndex::new(text);
+            for line in 0..5 {
+                assert_eq!(index.line(text, line), line_text(text, line), "line {} of {:?}", line, text);
+            }
+        }
+    }
+
+    #[test]
+    fn line_index_offsets_agree_with_offset_at() {
+        let text = "ab\né😀\nlast";
+        let index = LineIndex::new(text);
+        for line in 0..4 {
+            for character in 0..8 {
+                let position = Position { line, character };
+                assert_eq!(index.offset(text, &position), offset_at(text, &position), "{:?}", position);
+            }
+        }
+        for offset in (0..=text.len()).filter(|&offset| text.is_char_boundary(offset)) {
+            let position = index.position(offset);
+            assert_eq!(line_start(text, offset) + position.character as usize, offset);
+            assert_eq!(position.line as usize, text[..offset].matches('\n').count());
+        }
+    }
+}
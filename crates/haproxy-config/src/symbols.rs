@@ -0,0 +1,356 @@
+//! Names that sections, ACLs and servers declare, and where they are used.
+
+use std::collections::HashMap;
+
+use crate::config::{ConfigFile, Directive, SectionKind};
+use crate::position::{Position, Range};
+use crate::tokenizer::Token;
+
+/// A declared name together with every use of it that resolved to it.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub range: Range,
+    pub uri: String,
+    /// Index of the declaring section in the document's `ConfigFile`, for section-local symbols.
+    pub section: Option<usize>,
+    pub references: Vec<Reference>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub range: Range,
+    pub uri: String,
+    pub context: ReferenceContext,
+}
+
+/// The directive a reference appears in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReferenceContext {
+    UseBackend,
+    DefaultBackend,
+    AclCondition,
+    AclUnlessCondition,
+    ServerReference,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    Backend,
+    Frontend,
+    Listen,
+    Acl,
+    Server,
+}
+
+impl SymbolKind {
+    /// ACLs are local to their proxy section and servers to their backend.
+    pub fn is_section_local(&self) -> bool {
+        matches!(self, SymbolKind::Acl | SymbolKind::Server)
+    }
+
+    /// Whether a symbol of this kind answers a reference to `wanted`: a `listen`
+    /// section is a backend too.
+    pub fn satisfies(&self, wanted: &SymbolKind) -> bool {
+        self == wanted || (*self == SymbolKind::Listen && *wanted == SymbolKind::Backend)
+    }
+}
+
+/// ACLs HAProxy defines itself, usable in any condition without an `acl` line.
+pub const PREDEFINED_ACLS: &[&str] = &[
+    "FALSE",
+    "HTTP",
+    "HTTP_1.0",
+    "HTTP_1.1",
+    "HTTP_2.0",
+    "HTTP_3.0",
+    "HTTP_CONTENT",
+    "HTTP_URL_ABS",
+    "HTTP_URL_SLASH",
+    "HTTP_URL_STAR",
+    "LOCALHOST",
+    "METH_CONNECT",
+    "METH_DELETE",
+    "METH_GET",
+    "METH_HEAD",
+    "METH_OPTIONS",
+    "METH_POST",
+    "METH_PUT",
+    "METH_TRACE",
+    "RDP_COOKIE",
+    "REQ_CONTENT",
+    "TRUE",
+    "WAIT_END",
+];
+
+/// What a token means for navigation: the name a section, ACL or server declares, or a use of one.
+#[derive(Debug, Clone)]
+pub enum TokenRole {
+    Definition(SymbolKind),
+    Reference(SymbolKind, ReferenceContext),
+}
+
+impl Directive {
+    /// Every token of this directive that declares or references a symbol.
+    pub fn symbol_tokens(&self) -> Vec<(Token, TokenRole)> {
+        let mut tokens = Vec::new();
+        let first_arg = self.args.first().cloned();
+
+        match self.keyword.text.as_str() {
+            "acl" => tokens.extend(first_arg.map(|name| (name, TokenRole::Definition(SymbolKind::Acl)))),
+            "server" => tokens.extend(first_arg.map(|name| (name, TokenRole::Definition(SymbolKind::Server)))),
+            "use_backend" => tokens.extend(first_arg.map(|name| {
+                (name, TokenRole::Reference(SymbolKind::Backend, ReferenceContext::UseBackend))
+            })),
+            "default_backend" => tokens.extend(first_arg.map(|name| {
+                (name, TokenRole::Reference(SymbolKind::Backend, ReferenceContext::DefaultBackend))
+            })),
+            "use-server" => tokens.extend(first_arg.map(|name| {
+                (name, TokenRole::Reference(SymbolKind::Server, ReferenceContext::ServerReference))
+            })),
+            keyword => {
+                let kind = SectionKind::from_keyword(keyword).and_then(SectionKind::symbol_kind);
+                if let (Some(kind), Some(name)) = (kind, first_arg) {
+                    tokens.push((name, TokenRole::Definition(kind)));
+                }
+            }
+        }
+
+        if let Some((condition_type, condition)) = self.condition() {
+            let context = if condition_type.text == "unless" {
+                ReferenceContext::AclUnlessCondition
+            } else {
+                ReferenceContext::AclCondition
+            };
+            for acl_name in acl_names_in_condition(condition) {
+                tokens.push((acl_name, TokenRole::Reference(SymbolKind::Acl, context.clone())));
+            }
+        }
+
+        tokens
+    }
+}
+
+/// The named ACLs a condition refers to, with negations stripped and anonymous
+/// `{ ... }` ACLs skipped.
+fn acl_names_in_condition(condition: &[Token]) -> Vec<Token> {
+    let mut acl_names = Vec::new();
+    let mut brace_depth = 0;
+
+    for part in condition {
+        match part.text.as_str() {
+            // `!{` negates an anonymous ACL just like `! {`
+            "{" | "!{" => brace_depth += 1,
+            "}" => brace_depth -= 1,
+            "||" | "or" | "!" => {}
+            _ if brace_depth > 0 => {}
+            text => {
+                let name = text.trim_start_matches('!');
+                if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || "_-.:".contains(c)) {
+                    continue;
+                }
+                let mut token = part.clone();
+                if token.is_verbatim() {
+                    let negation = text.len() - name.len();
+                    token.span.start += negation;
+                    token.range.start.character += negation as u32;
+                }
+                token.text = name.to_string();
+                acl_names.push(token);
+            }
+        }
+    }
+
+    acl_names
+}
+
+/// The symbols `config` declares, each with the references in `config` that resolve to it.
+pub fn collect_symbols(uri: &str, config: &ConfigFile) -> Vec<Symbol> {
+    collect_symbols_with(uri, config, &Directive::symbol_tokens)
+}
+
+/// Like [`collect_symbols`], with `symbol_tokens` deciding which tokens of a directive
+/// declare or use a name, e.g. as an editor grammar classifies them.
+pub fn collect_symbols_with(
+    uri: &str,
+    config: &ConfigFile,
+    symbol_tokens: &dyn Fn(&Directive) -> Vec<(Token, TokenRole)>,
+) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    let mut references = Vec::new();
+
+    for directive in config.directives() {
+        for (token, role) in symbol_tokens(directive) {
+            match role {
+                TokenRole::Definition(kind) => symbols.push(Symbol {
+                    name: token.text,
+                    section: if kind.is_section_local() { directive.section } else { None },
+                    kind,
+                    range: token.range,
+                    uri: uri.to_string(),
+                    references: Vec::new(),
+                }),
+                TokenRole::Reference(kind, context) => references.push((
+                    token.text,
+                    config.lookup_scopes(directive.section, &kind),
+                    kind,
+                    Reference { range: token.range, uri: uri.to_string(), context },
+                )),
+            }
+        }
+    }
+
+    let index = SymbolIndex::new(&symbols);
+    let resolved: Vec<(usize, Reference)> = references
+        .into_iter()
+        .filter_map(|(name, scopes, kind, reference)| Some((index.resolve(&name, &kind, &scopes)?, reference)))
+        .collect();
+    for (symbol, reference) in resolved {
+        symbols[symbol].references.push(reference);
+    }
+    symbols
+}
+
+/// Index of the symbol `name` resolves to. Section-local symbols are only found in
+/// `scopes`, searched in order; proxies are visible everywhere.
+pub fn resolve_symbol(symbols: &[Symbol], name: &str, kind: &SymbolKind, scopes: &[usize]) -> Option<usize> {
+    SymbolIndex::new(symbols).resolve(name, kind, scopes)
+}
+
+/// Symbols by name, kind and declaring section, for resolving many names without
+/// scanning every symbol each time.
+pub struct SymbolIndex<'a> {
+    /// The first declaration of each name; proxies are keyed without a section.
+    first: HashMap<(&'a str, SymbolKind, Option<usize>), usize>,
+}
+
+impl<'a> SymbolIndex<'a> {
+    pub fn new(symbols: &'a [Symbol]) -> Self {
+        let mut first = HashMap::new();
+        for (index, symbol) in symbols.iter().enumerate() {
+            first.entry((symbol.name.as_str(), symbol.kind.clone(), symbol.section)).or_insert(index);
+        }
+        SymbolIndex { first }
+    }
+
+    /// Like [`resolve_symbol`] on the indexed symbols.
+    pub fn resolve(&self, name: &str, kind: &SymbolKind, scopes: &[usize]) -> Option<usize> {
+        if kind.is_section_local() {
+            return scopes.iter().find_map(|&scope| self.first.get(&(name, kind.clone(), Some(scope))).copied());
+        }
+        // A `listen` section answers a backend reference too; the earlier one wins
+        let listen = Some(SymbolKind::Listen).filter(|_| *kind == SymbolKind::Backend);
+        std::iter::once(kind.clone())
+            .chain(listen)
+            .filter_map(|kind| self.first.get(&(name, kind, None)).copied())
+            .min()
+    }
+}
+
+/// The declared or used name at `position`, its kind and the sections to resolve it in.
+pub fn symbol_token_at(config: &ConfigFile, position: &Position) -> Option<(Token, SymbolKind, Vec<usize>)> {
+    symbol_token_at_with(config, position, &Directive::symbol_tokens)
+}
+
+/// Like [`symbol_token_at`], with tokens classified by `symbol_tokens`.
+pub fn symbol_token_at_with(
+    config: &ConfigFile,
+    position: &Position,
+    symbol_tokens: &dyn Fn(&Directive) -> Vec<(Token, TokenRole)>,
+) -> Option<(Token, SymbolKind, Vec<usize>)> {
+    let directive = config.directive_at(position.line)?;
+    let (token, role) = symbol_tokens(directive)
+        .into_iter()
+        .find(|(token, _)| token.contains(position))?;
+
+    Some(match role {
+        TokenRole::Definition(kind) => (token, kind, directive.section.into_iter().collect()),
+        TokenRole::Reference(kind, _) => {
+            let scopes = config.lookup_scopes(directive.section, &kind);
+            (token, kind, scopes)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+defaults base
+  acl from_defaults src 10.0.0.0/8
+frontend fe
+  acl local path /x
+  acl from_defaults path /shadowed
+  use_backend be if local from_defaults
+frontend other
+  acl other_only path /y
+backend be
+  server s1 10.0.0.1:80
+backend be
+  server s2 10.0.0.2:80
+listen ls
+  server s1 10.0.0.3:80
+";
+
+    /// Section and line of the symbol `name` resolves to when used in `section`.
+    fn resolve(
+        symbols: &[Symbol],
+        config: &ConfigFile,
+        name: &str,
+        kind: SymbolKind,
+        section: usize,
+    ) -> Option<(Option<usize>, u32)> {
+        let scopes = config.lookup_scopes(Some(section), &kind);
+        let symbol = &symbols[resolve_symbol(symbols, name, &kind, &scopes)?];
+        Some((symbol.section, symbol.range.start.line))
+    }
+
+    #[test]
+    fn section_local_names_resolve_in_their_section_then_its_defaults() {
+        let config = ConfigFile::parse(CONFIG);
+        let symbols = collect_symbols("haproxy.cfg", &config);
+
+        // The frontend's own ACL shadows the one from defaults
+        assert_eq!(resolve(&symbols, &config, "from_defaults", SymbolKind::Acl, 1), Some((Some(1), 4)));
+        assert_eq!(resolve(&symbols, &config, "from_defaults", SymbolKind::Acl, 2), Some((Some(0), 1)));
+        assert_eq!(resolve(&symbols, &config, "local", SymbolKind::Acl, 1), Some((Some(1), 3)));
+        // Not visible from another proxy
+        assert_eq!(resolve(&symbols, &config, "local", SymbolKind::Acl, 2), None);
+        assert_eq!(resolve(&symbols, &config, "other_only", SymbolKind::Acl, 1), None);
+        // Servers are never inherited and stay in their backend
+        assert_eq!(resolve(&symbols, &config, "s1", SymbolKind::Server, 3), Some((Some(3), 9)));
+        assert_eq!(resolve(&symbols, &config, "s1", SymbolKind::Server, 5), Some((Some(5), 13)));
+        assert_eq!(resolve(&symbols, &config, "s2", SymbolKind::Server, 3), None);
+    }
+
+    #[test]
+    fn proxies_resolve_anywhere_to_their_first_declaration() {
+        let config = ConfigFile::parse(CONFIG);
+        let symbols = collect_symbols("haproxy.cfg", &config);
+
+        assert_eq!(resolve(&symbols, &config, "be", SymbolKind::Backend, 1), Some((None, 8)));
+        assert_eq!(resolve(&symbols, &config, "be", SymbolKind::Backend, 2), Some((None, 8)));
+        // A listen section is a backend, but not a frontend
+        assert_eq!(resolve(&symbols, &config, "ls", SymbolKind::Backend, 1), Some((None, 12)));
+        assert_eq!(resolve(&symbols, &config, "ls", SymbolKind::Frontend, 1), None);
+        assert_eq!(resolve(&symbols, &config, "fe", SymbolKind::Backend, 1), None);
+    }
+
+    #[test]
+    fn references_attach_to_the_symbol_they_resolve_to() {
+        let config = ConfigFile::parse(CONFIG);
+        let symbols = collect_symbols("haproxy.cfg", &config);
+        let references = |name: &str, line: u32| {
+            let symbol = symbols.iter().find(|symbol| symbol.name == name && symbol.range.start.line == line).unwrap();
+            symbol.references.iter().map(|reference| reference.range.start.line).collect::<Vec<_>>()
+        };
+
+        assert_eq!(references("local", 3), [5]);
+        assert_eq!(references("from_defaults", 4), [5]);
+        assert_eq!(references("from_defaults", 1), Vec::<u32>::new());
+        assert_eq!(references("be", 8), [5]);
+        assert_eq!(references("be", 10), Vec::<u32>::new());
+    }
+}
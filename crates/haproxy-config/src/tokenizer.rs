@@ -0,0 +1,271 @@
+//! Splitting configuration text into arguments the way HAProxy does.
+
+use crate::position::{Position, Range};
+
+/// A single argument of a config line together with where it sits in the document.
+#[derive(Debug, Clone)]
+pub struct Token {
+    /// The argument's value with quotes removed and escapes resolved.
+    pub text: String,
+    /// Byte offsets of the token as written, quotes and escapes included.
+    pub span: std::ops::Range<usize>,
+    pub range: Range,
+}
+
+impl Token {
+    /// Whether the token is written exactly as its value, without quotes or escapes.
+    pub fn is_verbatim(&self) -> bool {
+        // Every quote or escape consumes more bytes than it produces
+        self.span.len() == self.text.len()
+    }
+
+    /// Moves the token down by `lines` lines and `bytes` bytes, e.g. after text was
+    /// inserted above it.
+    pub fn shift(&mut self, lines: i64, bytes: i64) {
+        self.range.start.line = (self.range.start.line as i64 + lines) as u32;
+        self.range.end.line = (self.range.end.line as i64 + lines) as u32;
+        self.span = (self.span.start as i64 + bytes) as usize..(self.span.end as i64 + bytes) as usize;
+    }
+
+    /// Whether `position` is on the token, its end included.
+    pub fn contains(&self, position: &Position) -> bool {
+        self.range.start.line == position.line
+            && self.range.start.character <= position.character
+            && position.character <= self.range.end.character
+    }
+}
+
+/// Splits configuration text into logical lines of tokens the way HAProxy reads them.
+///
+/// Arguments are separated by unquoted whitespace. Single quotes keep their content
+/// verbatim, double quotes and bare words honor backslash escapes (`\ `, `\#`, `\"`,
+/// `\'`, `\\`, `\$`, `\n`, `\r`, `\t` and `\xHH` for ASCII bytes) and keep the backslash
+/// of any other, and quoted and bare parts next to each other form one argument.
+/// `${VAR}` references are kept as written. An unquoted `#` starts a comment, and a
+/// backslash at the end of a line continues the directive on the next.
+pub fn tokenize(content: &str) -> Vec<Vec<Token>> {
+    tokenize_continued(content).0
+}
+
+/// Like [`tokenize`], also telling whether `content` ends on a line continuation,
+/// i.e. its last directive goes on in whatever text follows.
+pub(crate) fn tokenize_continued(content: &str) -> (Vec<Vec<Token>>, bool) {
+    let mut lines = Vec::new();
+    let mut continued = false;
+    let mut tokens = Vec::new();
+    let mut pending: Option<PendingToken> = None;
+    let mut quote: Option<char> = None;
+    let mut in_comment = false;
+    let mut line = 0u32;
+    let mut line_start = 0usize;
+    let mut chars = content.char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        let position = Position { line, character: (offset - line_start) as u32 };
+
+        if c == '\n' {
+            // Quotes never span lines; an unterminated one ends with the line
+            if let Some(token) = pending.take() {
+                tokens.push(token.finish(offset, position));
+            }
+            if !tokens.is_empty() {
+                lines.push(std::mem::take(&mut tokens));
+            }
+            quote = None;
+            in_comment = false;
+            continued = false;
+            line += 1;
+            line_start = offset + 1;
+            continue;
+        }
+        if in_comment {
+            continue;
+        }
+
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), _) => pending.get_or_insert_with(|| PendingToken::new(offset, &position)).text.push(c),
+            (None, '\\') if matches!(chars.peek(), Some((_, '\n'))) || continues_line(content, offset) => {
+                // Line continuation: the directive goes on, the argument doesn't
+                if let Some(token) = pending.take() {
+                    tokens.push(token.finish(offset, position));
+                }
+                continued = true;
+                for (next_offset, next) in chars.by_ref() {
+                    if next == '\n' {
+                        line += 1;
+                        line_start = next_offset + 1;
+                        break;
+                    }
+                }
+            }
+            (_, '\\') => {
+                let token = pending.get_or_insert_with(|| PendingToken::new(offset, &position));
+                unescape(&mut chars, &mut token.text);
+            }
+            (Some(_), _) => pending.get_or_insert_with(|| PendingToken::new(offset, &position)).text.push(c),
+            (None, '\'') | (None, '"') => {
+                pending.get_or_insert_with(|| PendingToken::new(offset, &position));
+                quote = Some(c);
+            }
+            (None, '#') => {
+                if let Some(token) = pending.take() {
+                    tokens.push(token.finish(offset, position));
+                }
+                in_comment = true;
+            }
+            (None, _) if c.is_whitespace() => {
+                if let Some(token) = pending.take() {
+                    tokens.push(token.finish(offset, position));
+                }
+            }
+            (None, _) => pending.get_or_insert_with(|| PendingToken::new(offset, &position)).text.push(c),
+        }
+    }
+
+    if let Some(token) = pending.take() {
+        let position = Position { line, character: (content.len() - line_start) as u32 };
+        tokens.push(token.finish(content.len(), position));
+    }
+    if !tokens.is_empty() {
+        lines.push(tokens);
+    }
+    (lines, continued)
+}
+
+/// Whether the backslash at `offset` is the last character of a CRLF-terminated line.
+fn continues_line(content: &str, offset: usize) -> bool {
+    content[offset + 1..].starts_with("\r\n")
+}
+
+/// Resolves the escape sequence following a backslash outside single quotes into
+/// `text`. Like HAProxy, a backslash before anything it doesn't know how to
+/// escape is kept, so `^/api\.v1` stays as written.
+fn unescape(chars: &mut std::iter::Peekable<std::str::CharIndices>, text: &mut String) {
+    // The resolved character and how many characters after the backslash it takes
+    let escaped = match chars.peek().map(|&(_, c)| c) {
+        Some(c @ (' ' | '#' | '\\' | '\'' | '"' | '$')) => Some((c, 1)),
+        Some('n') => Some(('\n', 1)),
+        Some('r') => Some(('\r', 1)),
+        Some('t') => Some(('\t', 1)),
+        Some('x') => {
+            let digits: String = chars.clone().skip(1).take(2).map(|(_, c)| c).collect();
+            // Other bytes aren't text on their own, so they stay escaped
+            u8::from_str_radix(&digits, 16)
+                .ok()
+                .filter(|byte| digits.len() == 2 && byte.is_ascii())
+                .map(|byte| (byte as char, 3))
+        }
+        _ => None,
+    };
+    match escaped {
+        Some((c, length)) => {
+            chars.nth(length - 1);
+            text.push(c);
+        }
+        None => text.push('\\'),
+    }
+}
+
+struct PendingToken {
+    text: String,
+    start: usize,
+    position: Position,
+}
+
+impl PendingToken {
+    fn new(start: usize, position: &Position) -> Self {
+        PendingToken { text: String::new(), start, position: position.clone() }
+    }
+
+    fn finish(self, end: usize, end_position: Position) -> Token {
+        Token {
+            text: self.text,
+            span: self.start..end,
+            range: Range { start: self.position, end: end_position },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(content: &str) -> Vec<Vec<String>> {
+        tokenize(content)
+            .into_iter()
+            .map(|line| line.into_iter().map(|token| token.text).collect())
+            .collect()
+    }
+
+    #[test]
+    fn splits_on_whitespace_and_skips_comments() {
+        let content = "global\n  # a comment\n\n  maxconn\t4096   # trailing\nmode http#glued\n";
+        assert_eq!(texts(content), [vec!["global"], vec!["maxconn", "4096"], vec!["mode", "http"]]);
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(texts(r#"a "b c" 'd e' "f"'g'h"#), [vec!["a", "b c", "d e", "fgh"]]);
+        assert_eq!(texts(r##"a "#b" '#c' d\#e"##), [vec!["a", "#b", "#c", "d#e"]]);
+        // Single quotes keep backslashes, double quotes resolve them
+        assert_eq!(texts(r#"a 'x\ty' "x\"y" 'x"y' "x'y""#), [vec!["a", r"x\ty", r#"x"y"#, r#"x"y"#, "x'y"]]);
+        // An unterminated quote ends with its line
+        assert_eq!(texts("a \"b c\nd"), [vec!["a", "b c"], vec!["d"]]);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(texts(r"a\ b c\\d \x41\x7e e\n\t\r"), [vec!["a b", r"c\d", "A~", "e\n\t\r"]]);
+        assert_eq!(texts(r#"\"q\" \'s\' \$HOME"#), [vec![r#""q""#, "'s'", "$HOME"]]);
+    }
+
+    #[test]
+    fn unknown_escapes_keep_their_backslash() {
+        assert_eq!(texts(r"path_reg ^/api\.v1 \1 \z"), [vec!["path_reg", r"^/api\.v1", r"\1", r"\z"]]);
+        assert_eq!(texts(r#""^Host:\ www\.(.*)""#), [vec![r"^Host: www\.(.*)"]]);
+        // A byte outside ASCII isn't a character on its own
+        assert_eq!(texts(r"\xe9 \x4 \xZZ"), [vec![r"\xe9", r"\x4", r"\xZZ"]]);
+    }
+
+    #[test]
+    fn line_continuations() {
+        let lines = tokenize("server s1 \\\n    10.0.0.1:80 check\nmode http\r\nbind \\\r\n :80\n");
+        let words: Vec<Vec<&str>> =
+            lines.iter().map(|line| line.iter().map(|token| token.text.as_str()).collect()).collect();
+        assert_eq!(words, [vec!["server", "s1", "10.0.0.1:80", "check"], vec!["mode", "http"], vec!["bind", ":80"]]);
+
+        let address = &lines[0][2];
+        assert_eq!(address.range.start, Position { line: 1, character: 4 });
+        assert_eq!(address.span, 16..27);
+        // A backslash inside quotes doesn't continue the line
+        assert_eq!(texts("a \"b\\\nc"), [vec!["a", "b\\"], vec!["c"]]);
+    }
+
+    #[test]
+    fn environment_variables_are_kept() {
+        let expected = [vec!["bind", "${IP}:${PORT}", "${HOST}:80", "${RAW}"]];
+        assert_eq!(texts(r#"bind "${IP}:${PORT}" ${HOST}:80 '${RAW}'"#), expected);
+    }
+
+    #[test]
+    fn spans_cover_tokens_as_written() {
+        let line = &tokenize("  use_backend \"be api\" if acl1 # comment")[0];
+        let written: Vec<(&str, std::ops::Range<usize>, u32, u32, bool)> = line
+            .iter()
+            .map(|token| {
+                let range = (token.range.start.character, token.range.end.character);
+                (token.text.as_str(), token.span.clone(), range.0, range.1, token.is_verbatim())
+            })
+            .collect();
+        assert_eq!(
+            written,
+            [
+                ("use_backend", 2..13, 2, 13, true),
+                ("be api", 14..22, 14, 22, false),
+                ("if", 23..25, 23, 25, true),
+                ("acl1", 26..30, 26, 30, true),
+            ]
+        );
+    }
+}
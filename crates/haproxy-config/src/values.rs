@@ -0,0 +1,358 @@
+//! Typed arguments: times, sizes, bounded integers and percentages, and which
+//! directives and options take them.
+
+use crate::config::Directive;
+use crate::tokenizer::Token;
+
+/// What an argument must look like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    /// Milliseconds by default, or a number with a `us`, `ms`, `s`, `m`, `h` or `d` unit.
+    Time,
+    /// Bytes by default, or a number with a `k`, `m` or `g` suffix.
+    Size,
+    Integer { min: i64, max: i64 },
+    /// A number up to `max`, optionally followed by `%`.
+    Percent { max: u32 },
+}
+
+/// Longest time HAProxy's millisecond timers hold.
+const MAX_TIME_MS: u64 = i32::MAX as u64;
+
+const fn at_least(min: i64) -> ValueKind {
+    ValueKind::Integer { min, max: i32::MAX as i64 }
+}
+
+const fn between(min: i64, max: i64) -> ValueKind {
+    ValueKind::Integer { min, max }
+}
+
+impl ValueKind {
+    /// Checks `value`, the argument of `name`, describing the problem if it is invalid.
+    pub fn check(&self, name: &str, value: &str) -> Result<(), String> {
+        let valid = match *self {
+            ValueKind::Time => match parse_time(value) {
+                Some(ms) if ms > MAX_TIME_MS => {
+                    return Err(format!(
+                        "`{}` is longer than the {} ms HAProxy timers can hold (about 24.8 days)",
+                        value, MAX_TIME_MS
+                    ))
+                }
+                parsed => parsed.is_some(),
+            },
+            ValueKind::Size => parse_size(value).is_some_and(|size| size <= u32::MAX as u64),
+            ValueKind::Integer { min, max } => value.parse::<i64>().is_ok_and(|n| (min..=max).contains(&n)),
+            ValueKind::Percent { max } => {
+                let number = value.strip_suffix('%').unwrap_or(value);
+                number.bytes().all(|b| b.is_ascii_digit()) && number.parse::<u32>().is_ok_and(|n| n <= max)
+            }
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("`{}` expects {}, got `{}`", name, self.expectation(), value))
+        }
+    }
+
+    fn expectation(&self) -> String {
+        match *self {
+            ValueKind::Time => "a time such as `5s` or `500ms` (units: us, ms, s, m, h, d)".to_string(),
+            ValueKind::Size => "a size such as `16384` or `16k` (suffixes: k, m, g)".to_string(),
+            ValueKind::Integer { min, max } if max == i32::MAX as i64 => format!("an integer of at least {}", min),
+            ValueKind::Integer { min, max } => format!("an integer between {} and {}", min, max),
+            ValueKind::Percent { max } => format!("a percentage between 0 and {}", max),
+        }
+    }
+}
+
+/// `value` in milliseconds, rounded up for microseconds like HAProxy does.
+pub fn parse_time(value: &str) -> Option<u64> {
+    let digits = value.bytes().take_while(u8::is_ascii_digit).count();
+    let number: u64 = value[..digits].parse().ok()?;
+    let multiplier = match &value[digits..] {
+        "us" => return Some(number.div_ceil(1000)),
+        "" | "ms" => 1,
+        "s" => 1000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        "d" => 86_400_000,
+        _ => return None,
+    };
+    // Saturating is enough: the result only has to compare as too long
+    Some(number.saturating_mul(multiplier))
+}
+
+/// `value` in bytes.
+pub fn parse_size(value: &str) -> Option<u64> {
+    let digits = value.bytes().take_while(u8::is_ascii_digit).count();
+    let number: u64 = value[..digits].parse().ok()?;
+    let multiplier = match &value[digits..] {
+        "" => 1,
+        "k" | "K" => 1 << 10,
+        "m" | "M" => 1 << 20,
+        "g" | "G" => 1 << 30,
+        _ => return None,
+    };
+    number.checked_mul(multiplier)
+}
+
+/// Directives whose argument after the given words is typed; `*` matches any word,
+/// e.g. the timeout name in `timeout client 30s`.
+pub static DIRECTIVE_VALUES: &[(&str, ValueKind)] = &[
+    ("accepted_payload_size", between(512, 65535)),
+    ("backlog", at_least(0)),
+    ("clitcpka-cnt", at_least(0)),
+    ("clitcpka-idle", ValueKind::Time),
+    ("clitcpka-intvl", ValueKind::Time),
+    ("close-spread-time", ValueKind::Time),
+    ("fd-hard-limit", at_least(0)),
+    ("fullconn", at_least(0)),
+    ("hard-stop-after", ValueKind::Time),
+    ("hash-balance-factor", at_least(0)),
+    ("hold *", ValueKind::Time),
+    ("max-age", at_least(0)),
+    ("max-keep-alive-queue", at_least(-1)),
+    ("max-object-size", at_least(0)),
+    ("max-spread-checks", ValueKind::Time),
+    ("maxcompcpuusage", ValueKind::Percent { max: 100 }),
+    ("maxcomprate", at_least(0)),
+    ("maxconn", at_least(0)),
+    ("maxconnrate", at_least(0)),
+    ("maxlen", at_least(1)),
+    ("maxpipes", at_least(0)),
+    ("maxsessrate", at_least(0)),
+    ("maxsslconn", at_least(0)),
+    ("maxsslrate", at_least(0)),
+    ("maxzlibmem", at_least(0)),
+    ("mworker-max-reloads", at_least(0)),
+    ("nbthread", at_least(1)),
+    ("rate-limit sessions", at_least(0)),
+    ("resolve_retries", at_least(1)),
+    ("retries", at_least(0)),
+    ("size", ValueKind::Size),
+    ("spread-checks", ValueKind::Percent { max: 50 }),
+    ("srvtcpka-cnt", at_least(0)),
+    ("srvtcpka-idle", ValueKind::Time),
+    ("srvtcpka-intvl", ValueKind::Time),
+    ("stats maxconn", at_least(1)),
+    ("stats refresh", ValueKind::Time),
+    ("stats timeout", ValueKind::Time),
+    ("tcp-request inspect-delay", ValueKind::Time),
+    ("tcp-response inspect-delay", ValueKind::Time),
+    ("thread-groups", between(1, 64)),
+    ("timeout *", ValueKind::Time),
+    ("total-max-size", between(0, 4095)),
+    ("tune.bufsize", ValueKind::Size),
+    ("tune.http.maxhdr", between(1, 32767)),
+    ("tune.idletimer", ValueKind::Time),
+    ("tune.lua.maxmem", at_least(0)),
+    ("tune.lua.service-timeout", ValueKind::Time),
+    ("tune.lua.session-timeout", ValueKind::Time),
+    ("tune.lua.task-timeout", ValueKind::Time),
+    ("tune.maxaccept", at_least(-1)),
+    ("tune.maxpollevents", at_least(1)),
+    ("tune.maxrewrite", ValueKind::Size),
+    ("tune.pipesize", ValueKind::Size),
+    ("tune.rcvbuf.client", ValueKind::Size),
+    ("tune.rcvbuf.server", ValueKind::Size),
+    ("tune.sndbuf.client", ValueKind::Size),
+    ("tune.sndbuf.server", ValueKind::Size),
+    ("tune.ssl.cachesize", at_least(0)),
+    ("tune.ssl.default-dh-param", at_least(1024)),
+    ("tune.ssl.lifetime", ValueKind::Time),
+    ("tune.ssl.maxrecord", ValueKind::Size),
+    ("tune.zlib.memlevel", between(1, 9)),
+    ("tune.zlib.windowsize", between(8, 15)),
+    ("ulimit-n", at_least(0)),
+];
+
+/// `server`, `default-server` and `server-template` options taking a typed value.
+pub static SERVER_OPTIONS: &[(&str, ValueKind)] = &[
+    ("agent-inter", ValueKind::Time),
+    ("agent-port", between(1, 65535)),
+    ("downinter", ValueKind::Time),
+    ("error-limit", at_least(0)),
+    ("fall", at_least(1)),
+    ("fastinter", ValueKind::Time),
+    ("inter", ValueKind::Time),
+    ("max-reuse", at_least(-1)),
+    ("maxconn", at_least(0)),
+    ("maxqueue", at_least(0)),
+    ("minconn", at_least(0)),
+    ("pool-low-conn", at_least(0)),
+    ("pool-max-conn", at_least(-1)),
+    ("pool-purge-delay", ValueKind::Time),
+    ("port", between(1, 65535)),
+    ("rise", at_least(1)),
+    ("slowstart", ValueKind::Time),
+    ("tcp-ut", ValueKind::Time),
+    ("weight", between(0, 256)),
+];
+
+/// `bind` options taking a typed value.
+pub static BIND_OPTIONS: &[(&str, ValueKind)] = &[
+    ("backlog", at_least(0)),
+    ("maxconn", at_least(0)),
+    ("nice", between(-1024, 1024)),
+    ("tcp-ut", ValueKind::Time),
+];
+
+impl Directive {
+    /// Every typed argument of the directive, with the name it belongs to and its kind.
+    pub fn typed_values(&self) -> Vec<(String, &Token, ValueKind)> {
+        let mut values = Vec::new();
+        let words: Vec<&str> = std::iter::once(&self.keyword)
+            .chain(&self.args)
+            .map(|token| token.text.as_str())
+            .collect();
+
+        for (pattern, kind) in DIRECTIVE_VALUES {
+            let count = pattern.split(' ').count();
+            let matches = words.len() > count
+                && pattern.split(' ').zip(&words).all(|(expected, word)| expected == "*" || expected == *word);
+            if matches {
+                values.push((words[..count].join(" "), &self.args[count - 1], *kind));
+                break;
+            }
+        }
+
+        // Options follow the name and address of a server
+        let (options, skip) = match self.keyword.text.as_str() {
+            "server" => (SERVER_OPTIONS, 2),
+            "server-template" => (SERVER_OPTIONS, 3),
+            "default-server" => (SERVER_OPTIONS, 0),
+            "bind" => (BIND_OPTIONS, 1),
+            _ => return values,
+        };
+        let args = self.args.get(skip..).unwrap_or_default();
+        for pair in args.windows(2) {
+            if let Some((name, kind)) = options.iter().find(|(name, _)| *name == pair[0].text) {
+                values.push((name.to_string(), &pair[1], *kind));
+            }
+        }
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigFile;
+
+    #[test]
+    fn times() {
+        let cases = [
+            ("0", Some(0)),
+            ("500", Some(500)),
+            ("500ms", Some(500)),
+            ("5s", Some(5_000)),
+            ("2m", Some(120_000)),
+            ("1h", Some(3_600_000)),
+            ("1d", Some(86_400_000)),
+            // Microseconds round up to the next millisecond
+            ("1us", Some(1)),
+            ("1000us", Some(1)),
+            ("1001us", Some(2)),
+            ("0us", Some(0)),
+            ("50x", None),
+            ("5S", None),
+            ("s", None),
+            ("", None),
+            ("-5s", None),
+            ("1.5s", None),
+            ("99999999999999999999d", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_time(value), expected, "{:?}", value);
+        }
+        // Saturates instead of overflowing
+        assert_eq!(parse_time("18446744073709551615d"), Some(u64::MAX));
+    }
+
+    #[test]
+    fn sizes() {
+        let cases = [
+            ("16384", Some(16_384)),
+            ("16k", Some(16_384)),
+            ("16K", Some(16_384)),
+            ("2m", Some(2 << 20)),
+            ("1g", Some(1 << 30)),
+            ("16q", None),
+            ("16kb", None),
+            ("k", None),
+            ("", None),
+            ("18446744073709551615k", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_size(value), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn checks() {
+        let valid = [
+            (ValueKind::Time, "50s"),
+            (ValueKind::Time, "2147483647"),
+            (ValueKind::Time, "24d"),
+            (ValueKind::Size, "16k"),
+            (ValueKind::Size, "4294967295"),
+            (between(0, 256), "256"),
+            (at_least(1), "1"),
+            (at_least(-1), "-1"),
+            (ValueKind::Percent { max: 100 }, "100%"),
+            (ValueKind::Percent { max: 50 }, "25"),
+        ];
+        for (kind, value) in valid {
+            assert_eq!(kind.check("x", value), Ok(()), "{:?} {:?}", kind, value);
+        }
+
+        let invalid = [
+            (ValueKind::Time, "50x"),
+            // Past the 2^31-1 ms timers
+            (ValueKind::Time, "2147483648"),
+            (ValueKind::Time, "25d"),
+            (ValueKind::Size, "4g"),
+            (between(0, 256), "300"),
+            (at_least(1), "0"),
+            (at_least(0), "ten"),
+            (ValueKind::Percent { max: 100 }, "101%"),
+            (ValueKind::Percent { max: 50 }, "+5"),
+            (ValueKind::Percent { max: 50 }, "%"),
+        ];
+        for (kind, value) in invalid {
+            assert!(kind.check("x", value).is_err(), "{:?} {:?}", kind, value);
+        }
+
+        assert_eq!(
+            between(0, 256).check("weight", "300").unwrap_err(),
+            "`weight` expects an integer between 0 and 256, got `300`"
+        );
+        assert!(ValueKind::Time.check("timeout client", "25d").unwrap_err().contains("24.8 days"));
+    }
+
+    /// The typed values of the single directive in `line`, as (name, value, kind).
+    fn typed(line: &str) -> Vec<(String, String, ValueKind)> {
+        let config = ConfigFile::parse(&format!("backend b\n  {}\n", line));
+        let directive = &config.sections[0].directives[0];
+        directive.typed_values().into_iter().map(|(name, token, kind)| (name, token.text.clone(), kind)).collect()
+    }
+
+    #[test]
+    fn typed_values() {
+        let value = |name: &str, value: &str, kind| (name.to_string(), value.to_string(), kind);
+
+        assert_eq!(typed("timeout server 30s"), [value("timeout server", "30s", ValueKind::Time)]);
+        assert_eq!(typed("timeout"), []);
+        assert_eq!(typed("retries 3"), [value("retries", "3", at_least(0))]);
+        assert_eq!(
+            typed("server s1 10.0.0.1:80 weight 300 rise 0 check"),
+            [value("weight", "300", between(0, 256)), value("rise", "0", at_least(1))]
+        );
+        // The server name and address are never options, even when they look like one
+        assert_eq!(typed("server weight 10.0.0.1:80"), []);
+        assert_eq!(typed("server-template weight 3 rise:80 fall 2"), [value("fall", "2", at_least(1))]);
+        assert_eq!(typed("default-server inter 3s"), [value("inter", "3s", ValueKind::Time)]);
+        assert_eq!(typed("bind :80 maxconn 100"), [value("maxconn", "100", at_least(0))]);
+        assert_eq!(typed("http-request deny"), []);
+    }
+}
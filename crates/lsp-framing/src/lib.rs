@@ -0,0 +1,241 @@
+//! The LSP base protocol: messages made of a block of `Name: value` headers ended
+//! by an empty line, followed by exactly `Content-Length` bytes of content.
+//!
+//! Both sides of a connection use it, the server and test clients alike.
+//!
+//! ```
+//! use lsp_framing::{write_message, MessageReader};
+//!
+//! let mut stream = Vec::new();
+//! write_message(&mut stream, br#"{"jsonrpc":"2.0","method":"exit"}"#).unwrap();
+//!
+//! let mut reader = MessageReader::new(stream.as_slice());
+//! assert_eq!(reader.read_message().unwrap().unwrap(), br#"{"jsonrpc":"2.0","method":"exit"}"#);
+//! assert!(reader.read_message().unwrap().is_none());
+//! ```
+
+use std::io::{self, BufRead, Write};
+
+const CONTENT_LENGTH: &str = "Content-Length";
+
+/// Largest content accepted, so a corrupt or hostile header can't make the reader
+/// allocate an arbitrary amount of memory up front.
+pub const MAX_CONTENT_LENGTH: usize = 64 * 1024 * 1024;
+
+/// Reads messages one after the other from a stream.
+pub struct MessageReader<R> {
+    reader: R,
+    /// Set after a header block without a usable `Content-Length`: where its content
+    /// ends is unknown, so the next read first skips to the next such header.
+    lost_sync: bool,
+}
+
+impl<R: BufRead> MessageReader<R> {
+    pub fn new(reader: R) -> Self {
+        MessageReader { reader, lost_sync: false }
+    }
+
+    /// The next message's content, or `None` at a clean end of input.
+    ///
+    /// Header names are matched case-insensitively and headers other than
+    /// `Content-Length`, like `Content-Type`, are ignored. A message with malformed
+    /// headers is reported as `InvalidData` and the caller may simply read on: if
+    /// its length is known its content is skipped, otherwise the reader resumes at
+    /// the next `Content-Length` header in the stream. A length above
+    /// [`MAX_CONTENT_LENGTH`] counts as malformed. End of input inside a header block
+    /// or a message is `UnexpectedEof`.
+    pub fn read_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut line = match self.lost_sync {
+            true => match self.skip_to_content_length()? {
+                Some(line) => line,
+                None => return Ok(None),
+            },
+            false => String::new(),
+        };
+        self.lost_sync = false;
+
+        let mut content_length = None;
+        let mut header_error = None;
+        let mut read_any = false;
+        loop {
+            if line.is_empty() && self.reader.read_line(&mut line)? == 0 {
+                if read_any {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "end of input inside message headers"));
+                }
+                return Ok(None);
+            }
+
+            let header = line.trim_end_matches(['\r', '\n']);
+            if header.is_empty() {
+                line.clear();
+                // Stray blank lines between messages are harmless
+                if !read_any {
+                    continue;
+                }
+                break;
+            }
+            read_any = true;
+
+            match header.split_once(':') {
+                Some((name, value)) if name.trim().eq_ignore_ascii_case(CONTENT_LENGTH) => {
+                    match value.trim().parse::<usize>() {
+                        Ok(length) if length > MAX_CONTENT_LENGTH => {
+                            header_error = Some(format!("Content-Length {} is over the limit", length))
+                        }
+                        Ok(length) => content_length = Some(length),
+                        Err(_) => header_error = Some(format!("invalid Content-Length: {}", value.trim())),
+                    }
+                }
+                // Content-Type and any other header carry nothing we need
+                Some(_) => {}
+                None => header_error = Some(format!("malformed header line: {}", header)),
+            }
+            line.clear();
+        }
+
+        let content_length = match (content_length, header_error) {
+            (Some(length), error) => {
+                let mut content = vec![0; length];
+                self.reader.read_exact(&mut content)?;
+                match error {
+                    Some(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+                    None => return Ok(Some(content)),
+                }
+            }
+            (None, error) => error.unwrap_or_else(|| "missing Content-Length header".to_string()),
+        };
+        self.lost_sync = true;
+        Err(io::Error::new(io::ErrorKind::InvalidData, content_length))
+    }
+
+    /// Discards input up to the next `Content-Length` header, which content without
+    /// a trailing newline may directly precede, and returns that header's line.
+    fn skip_to_content_length(&mut self) -> io::Result<Option<String>> {
+        let name = format!("{}:", CONTENT_LENGTH.to_ascii_lowercase());
+        let name = name.as_bytes();
+        let mut matched = 0;
+
+        loop {
+            let buffer = self.reader.fill_buf()?;
+            if buffer.is_empty() {
+                return Ok(None);
+            }
+            let mut used = 0;
+            for &byte in buffer {
+                used += 1;
+                let byte = byte.to_ascii_lowercase();
+                // No proper prefix of the name reappears in it, so a mismatch restarts
+                matched = if byte == name[matched] {
+                    matched + 1
+                } else {
+                    usize::from(byte == name[0])
+                };
+                if matched == name.len() {
+                    break;
+                }
+            }
+            self.reader.consume(used);
+
+            if matched == name.len() {
+                let mut line = format!("{}:", CONTENT_LENGTH);
+                self.reader.read_line(&mut line)?;
+                return Ok(Some(line));
+            }
+        }
+    }
+}
+
+/// Writes `content` as one message with its `Content-Length` header.
+pub fn write_message(writer: &mut impl Write, content: &[u8]) -> io::Result<()> {
+    write!(writer, "{}: {}\r\n\r\n", CONTENT_LENGTH, content.len())?;
+    writer.write_all(content)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(input: &str) -> Vec<Result<String, io::ErrorKind>> {
+        let mut reader = MessageReader::new(input.as_bytes());
+        let mut messages = Vec::new();
+        loop {
+            match reader.read_message() {
+                Ok(Some(content)) => messages.push(Ok(String::from_utf8(content).unwrap())),
+                Ok(None) => return messages,
+                Err(err) => {
+                    let kind = err.kind();
+                    messages.push(Err(kind));
+                    if kind != io::ErrorKind::InvalidData {
+                        return messages;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reads_consecutive_messages() {
+        let input = "Content-Length: 2\r\n\r\n{}Content-Length: 4\r\n\r\nnull\r\n\r\nContent-Length: 1\r\n\r\n1";
+        assert_eq!(read_all(input), [Ok("{}".to_string()), Ok("null".to_string()), Ok("1".to_string())]);
+    }
+
+    #[test]
+    fn header_names_ignore_case_and_order() {
+        let input = "content-type: application/vscode-jsonrpc; charset=utf-8\r\nCONTENT-LENGTH:4\r\n\r\ntrue\
+                     Content-Length: 5\nContent-Type: application/json\n\nfalse";
+        assert_eq!(read_all(input), [Ok("true".to_string()), Ok("false".to_string())]);
+    }
+
+    #[test]
+    fn content_is_counted_in_bytes() {
+        let content = "\"é\u{1F600}\"";
+        let mut stream = Vec::new();
+        write_message(&mut stream, content.as_bytes()).unwrap();
+        write_message(&mut stream, b"{}").unwrap();
+        assert_eq!(read_all(std::str::from_utf8(&stream).unwrap()), [Ok(content.to_string()), Ok("{}".to_string())]);
+    }
+
+    #[test]
+    fn bad_length_is_skipped_up_to_the_next_message() {
+        let good = |id: u32| format!("{{\"id\":{}}}", id);
+        let framed = |id: u32| format!("Content-Length: {}\r\n\r\n{}", good(id).len(), good(id));
+        let input = format!("Content-Length: abc\r\n\r\n{}{}{}{}", good(0), framed(1), framed(2), framed(3));
+        assert_eq!(
+            read_all(&input),
+            [Err(io::ErrorKind::InvalidData), Ok(good(1)), Ok(good(2)), Ok(good(3))]
+        );
+
+        let input = format!("Content-Type: text/plain\r\n\r\nsome\nlines\n{}", framed(1));
+        assert_eq!(read_all(&input), [Err(io::ErrorKind::InvalidData), Ok(good(1))]);
+    }
+
+    #[test]
+    fn malformed_header_with_a_length_skips_only_its_content() {
+        let input = "Content-Length: 2\r\ngarbage\r\n\r\n{}Content-Length: 4\r\n\r\nnull";
+        assert_eq!(read_all(input), [Err(io::ErrorKind::InvalidData), Ok("null".to_string())]);
+    }
+
+    #[test]
+    fn oversized_length_is_rejected_before_reading_content() {
+        let input = format!("Content-Length: {}\r\n\r\n{{}}Content-Length: 4\r\n\r\nnull", MAX_CONTENT_LENGTH + 1);
+        assert_eq!(read_all(&input), [Err(io::ErrorKind::InvalidData), Ok("null".to_string())]);
+
+        let input = format!("Content-Length: {}\r\n\r\n", usize::MAX);
+        assert_eq!(read_all(&input), [Err(io::ErrorKind::InvalidData)]);
+    }
+
+    #[test]
+    fn end_of_input() {
+        assert_eq!(read_all(""), []);
+        assert_eq!(read_all("\r\n\r\n"), []);
+        assert_eq!(
+            read_all("Content-Length: 2\r\n\r\n{}Content-Len"),
+            [Ok("{}".to_string()), Err(io::ErrorKind::UnexpectedEof)]
+        );
+        assert_eq!(read_all("Content-Length: 2\r\nContent-Type: x\r\n"), [Err(io::ErrorKind::UnexpectedEof)]);
+        assert_eq!(read_all("Content-Length: 10\r\n\r\n{}"), [Err(io::ErrorKind::UnexpectedEof)]);
+        // Nothing left to resynchronise to
+        assert_eq!(read_all("Content-Length: x\r\n\r\n{}"), [Err(io::ErrorKind::InvalidData)]);
+    }
+}
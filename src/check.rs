@@ -0,0 +1,350 @@
+//! `haproxy-lsp check`: reports the diagnostics the server publishes for files on
+//! disk, so CI applies exactly the editor's rules.
+
+use haproxy_config::{Diagnostic, LineIndex, Position, PositionEncoding, Severity, Version};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{Command, HaproxyLsp};
+
+pub enum Format {
+    Human,
+    Json,
+    Sarif,
+}
+
+pub struct CheckOptions {
+    paths: Vec<PathBuf>,
+    format: Format,
+    haproxy_version: Option<Version>,
+}
+
+/// One checked file: the name it is reported under, its text and its findings.
+struct Report {
+    file: String,
+    text: String,
+    lines: LineIndex,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Report {
+    /// One-based line and column of `position`, the column counted in `encoding`.
+    fn line_column(&self, position: &Position, encoding: PositionEncoding) -> (u32, u32) {
+        let column = encoding.column(self.lines.line(&self.text, position.line), position.character);
+        (position.line + 1, column + 1)
+    }
+}
+
+/// Parses the arguments following `check`.
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut paths = Vec::new();
+    let mut format = Format::Human;
+    let mut haproxy_version = None;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg, None),
+        };
+        let mut value = |name: &str| {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} requires a value", name))
+        };
+
+        match flag.as_str() {
+            "-f" | "--file" => paths.push(PathBuf::from(value("-f")?)),
+            "--format" => {
+                format = match value("--format")?.as_str() {
+                    "human" => Format::Human,
+                    "json" => Format::Json,
+                    "sarif" => Format::Sarif,
+                    other => return Err(format!("unknown format: {}", other)),
+                }
+            }
+            "--haproxy-version" => haproxy_version = Some(value("--haproxy-version")?.parse()?),
+            "--help" | "-h" => return Ok(Command::Help),
+            other if other.starts_with('-') => return Err(format!("unknown argument: {}", other)),
+            // Bare paths work like `-f`
+            _ => paths.push(PathBuf::from(flag)),
+        }
+    }
+
+    if paths.is_empty() {
+        return Err("check needs at least one file (-f <path>)".to_string());
+    }
+    Ok(Command::Check(CheckOptions { paths, format, haproxy_version }))
+}
+
+/// Checks every file named in `options` together, so names defined in one file
+/// resolve in the others, prints the findings and returns the exit code: 1 if any
+/// error was found, 0 otherwise.
+pub fn run(options: &CheckOptions) -> Result<i32, Box<dyn std::error::Error>> {
+    let files = unique_config_files(&options.paths)?;
+
+    let mut lsp = HaproxyLsp::new()?;
+    lsp.workspace.haproxy_version = options.haproxy_version;
+    for file in &files {
+        let text = std::fs::read_to_string(file).map_err(|err| format!("{}: {}", file.display(), err))?;
+        lsp.parse_document(&file.display().to_string(), text, 0)?;
+    }
+
+    let reports: Vec<Report> = files
+        .iter()
+        .map(|file| {
+            let file = file.display().to_string();
+            let document = &lsp.workspace.documents[&file];
+            Report {
+                text: document.text.clone(),
+                lines: document.lines.clone(),
+                diagnostics: lsp.workspace.diagnostics(&file),
+                file,
+            }
+        })
+        .collect();
+
+    match write_reports(&mut io::stdout().lock(), &options.format, &reports) {
+        // Piped into e.g. `head`, which has seen enough
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {}
+        result => result.map_err(|err| format!("writing the report: {}", err))?,
+    }
+
+    let failed = reports
+        .iter()
+        .flat_map(|report| &report.diagnostics)
+        .any(|diagnostic| diagnostic.severity == Severity::Error);
+    Ok(if failed { 1 } else { 0 })
+}
+
+fn write_reports(out: &mut impl Write, format: &Format, reports: &[Report]) -> io::Result<()> {
+    match format {
+        Format::Human => write_human(out, reports)?,
+        Format::Json => writeln!(out, "{}", serde_json::to_string_pretty(&json_reports(reports))?)?,
+        Format::Sarif => writeln!(out, "{}", serde_json::to_string_pretty(&sarif_log(reports))?)?,
+    }
+    out.flush()
+}
+
+/// The config files `paths` name, each once however many times it is named, e.g.
+/// by `-f haproxy.cfg -f .` or through a symlink.
+fn unique_config_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+    for path in paths {
+        for file in config_files(path)? {
+            // A file that can't be resolved is kept for reading it to report why
+            if seen.insert(std::fs::canonicalize(&file).unwrap_or_else(|_| file.clone())) {
+                files.push(file);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// `path` itself, or the `*.cfg` files of a directory in name order the way
+/// `haproxy -f <dir>` loads them, hidden ones skipped like HAProxy does.
+fn config_files(path: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(path).map_err(|err| format!("{}: {}", path.display(), err))? {
+        let file = entry?.path();
+        let hidden = file.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if file.is_file() && !hidden && file.extension().is_some_and(|extension| extension == "cfg") {
+            files.push(file);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// One-based line and column of `position` in the checked file `file`.
+fn locate(reports: &[Report], file: &str, position: &Position, encoding: PositionEncoding) -> (u32, u32) {
+    match reports.iter().find(|report| report.file == file) {
+        Some(report) => report.line_column(position, encoding),
+        None => (position.line + 1, position.character + 1),
+    }
+}
+
+/// rustc-style output: the message, its location and the offending line underlined.
+fn write_human(out: &mut impl Write, reports: &[Report]) -> io::Result<()> {
+    let mut errors = 0;
+    let mut warnings = 0;
+
+    for report in reports {
+        for diagnostic in &report.diagnostics {
+            match diagnostic.severity {
+                Severity::Error => errors += 1,
+                Severity::Warning => warnings += 1,
+                _ => {}
+            }
+
+            let range = &diagnostic.range;
+            let (line, column) = report.line_column(&range.start, PositionEncoding::Utf32);
+            let source = report.lines.line(&report.text, range.start.line).trim_end_matches('\r');
+            let end_column = if range.end.line == range.start.line {
+                report.line_column(&range.end, PositionEncoding::Utf32).1
+            } else {
+                source.chars().count() as u32 + 1
+            };
+            let gutter = " ".repeat(line.to_string().len());
+
+            writeln!(out, "{}[{}]: {}", diagnostic.severity.name(), diagnostic.code, diagnostic.message)?;
+            writeln!(out, "{}--> {}:{}:{}", gutter, report.file, line, column)?;
+            writeln!(out, "{} |", gutter)?;
+            writeln!(out, "{} | {}", line, source)?;
+            writeln!(
+                out,
+                "{} | {}{}",
+                gutter,
+                " ".repeat(column as usize - 1),
+                "^".repeat(end_column.saturating_sub(column).max(1) as usize)
+            )?;
+            for related in &diagnostic.related {
+                let (line, column) = locate(reports, &related.uri, &related.range.start, PositionEncoding::Utf32);
+                writeln!(out, "{} = note: {} at {}:{}:{}", gutter, related.message, related.uri, line, column)?;
+            }
+            writeln!(out)?;
+        }
+    }
+
+    if errors + warnings > 0 {
+        writeln!(out, "{} error(s), {} warning(s) in {} file(s)", errors, warnings, reports.len())?;
+    }
+    Ok(())
+}
+
+fn json_reports(reports: &[Report]) -> Value {
+    let diagnostics: Vec<Value> = reports
+        .iter()
+        .flat_map(|report| {
+            report.diagnostics.iter().map(move |diagnostic| {
+                let (line, column) = report.line_column(&diagnostic.range.start, PositionEncoding::Utf32);
+                let (end_line, end_column) = report.line_column(&diagnostic.range.end, PositionEncoding::Utf32);
+                let related: Vec<Value> = diagnostic
+                    .related
+                    .iter()
+                    .map(|related| {
+                        let (line, column) = locate(reports, &related.uri, &related.range.start, PositionEncoding::Utf32);
+                        json!({
+                            "file": related.uri,
+                            "line": line,
+                            "column": column,
+                            "message": related.message
+                        })
+                    })
+                    .collect();
+                json!({
+                    "file": report.file,
+                    "line": line,
+                    "column": column,
+                    "endLine": end_line,
+                    "endColumn": end_column,
+                    "severity": diagnostic.severity.name(),
+                    "code": diagnostic.code,
+                    "message": diagnostic.message,
+                    "related": related
+                })
+            })
+        })
+        .collect();
+    json!(diagnostics)
+}
+
+/// A SARIF 2.1.0 log, which code scanning tools can annotate pull requests from.
+fn sarif_log(reports: &[Report]) -> Value {
+    let mut rules: Vec<&str> = reports
+        .iter()
+        .flat_map(|report| report.diagnostics.iter().map(|diagnostic| diagnostic.code))
+        .collect();
+    rules.sort();
+    rules.dedup();
+
+    let results: Vec<Value> = reports
+        .iter()
+        .flat_map(|report| {
+            report.diagnostics.iter().map(move |diagnostic| {
+                // SARIF counts columns in UTF-16 code units unless told otherwise
+                let (line, column) = report.line_column(&diagnostic.range.start, PositionEncoding::Utf16);
+                let (end_line, end_column) = report.line_column(&diagnostic.range.end, PositionEncoding::Utf16);
+                let level = match diagnostic.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                    Severity::Information | Severity::Hint => "note",
+                };
+                let related: Vec<Value> = diagnostic
+                    .related
+                    .iter()
+                    .enumerate()
+                    .map(|(id, related)| {
+                        let (line, column) = locate(reports, &related.uri, &related.range.start, PositionEncoding::Utf16);
+                        json!({
+                            "id": id,
+                            "message": { "text": related.message },
+                            "physicalLocation": {
+                                "artifactLocation": { "uri": related.uri },
+                                "region": { "startLine": line, "startColumn": column }
+                            }
+                        })
+                    })
+                    .collect();
+                json!({
+                    "ruleId": diagnostic.code,
+                    "level": level,
+                    "message": { "text": diagnostic.message },
+                    "locations": [{
+                        "physicalLocation": {
+                            "artifactLocation": { "uri": report.file },
+                            "region": {
+                                "startLine": line,
+                                "startColumn": column,
+                                "endLine": end_line,
+                                "endColumn": end_column
+                            }
+                        }
+                    }],
+                    "relatedLocations": related
+                })
+            })
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "haproxy-lsp",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules.iter().map(|rule| json!({ "id": rule })).collect::<Vec<Value>>()
+                }
+            },
+            "results": results
+        }]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_named_twice_are_checked_once() {
+        let dir = std::env::temp_dir().join(format!("haproxy-lsp-check-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("haproxy.cfg");
+        std::fs::write(&file, "global\n").unwrap();
+
+        let paths = [file.clone(), dir.clone(), dir.join(".").join("haproxy.cfg"), dir.join("missing.cfg")];
+        let files = unique_config_files(&paths).unwrap();
+        assert_eq!(files, vec![file, dir.join("missing.cfg")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -36,11 +36,11 @@ impl zed::Extension for HaproxyExtension {
         
         // Try to find haproxy-lsp in PATH (most reliable for installed version)
         eprintln!("HAProxy Extension: Using haproxy-lsp from PATH");
-        return Ok(Command {
+        Ok(Command {
             command: "haproxy-lsp".to_string(),
             args: vec!["--stdio".to_string()],
             env: Default::default(),
-        });
+        })
     }
 }
 
@@ -1,644 +1,1902 @@
+use haproxy_config::{
+    backend_uses_with, diagnose_with, migrate, parse_address, section_since, symbol_token_at_with, version_pragma,
+    ConfigFile, Diagnostic, DiagnosticTag, Directive, Edit, Keyword, LineIndex, Position, PositionEncoding, Range,
+    ReferenceContext, Reparsed, Section, SectionKind, Severity, Symbol, SymbolKind, Token, TokenRole, Version,
+    KEYWORDS,
+};
 use serde_json::{json, Value};
-use std::collections::HashMap;
-use std::io::{self, BufRead, Read, Write};
-use tree_sitter::{Parser};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use std::io::{self, BufRead, Write};
+use lsp_framing::MessageReader;
+use tree_sitter::{InputEdit, Language, Parser, Point, Tree};
 
-#[derive(Debug, Clone)]
-struct Symbol {
-    name: String,
-    kind: SymbolKind,
-    range: Range,
-    uri: String,
-    references: Vec<Reference>,
+mod check;
+
+#[cfg(haproxy_grammar)]
+extern "C" {
+    fn tree_sitter_haproxy() -> Language;
 }
 
-#[derive(Debug, Clone)]
-struct Reference {
-    range: Range,
-    uri: String,
-    context: ReferenceContext,
+/// The tree-sitter-haproxy language, if `build.rs` found and compiled the grammar.
+fn haproxy_language() -> Option<Language> {
+    #[cfg(haproxy_grammar)]
+    {
+        // SAFETY: the symbol is provided by the grammar compiled in build.rs.
+        Some(unsafe { tree_sitter_haproxy() })
+    }
+    #[cfg(not(haproxy_grammar))]
+    {
+        None
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-enum ReferenceContext {
-    UseBackend,
-    DefaultBackend,
-    AclCondition,
-    AclUnlessCondition,
-    ServerReference,
+/// The tree-sitter point of a position whose `character` counts bytes.
+fn point(position: Position) -> Point {
+    Point::new(position.line as usize, position.character as usize)
 }
 
-#[derive(Debug, Clone, PartialEq)]
-enum SymbolKind {
-    Backend,
-    Frontend,
-    Listen,
-    Acl,
-    Server,
+/// Picks the first position encoding in the client's preference order that the
+/// server speaks, falling back to UTF-16 as the specification requires.
+fn negotiate_position_encoding(client_capabilities: &Value) -> PositionEncoding {
+    client_capabilities["general"]["positionEncodings"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .find_map(PositionEncoding::from_name)
+        .unwrap_or_default()
 }
 
-#[derive(Debug, Clone)]
-struct Range {
-    start: Position,
-    end: Position,
+/// The names declared or used in `directive` as the tree-sitter grammar classifies
+/// them: `section_name`, `acl_name` and `server_name` nodes of the declaring
+/// directives, and `backend_ref` and condition `acl_name` nodes. `None` when the
+/// grammar doesn't model the directive (it knows `use_backend` but not
+/// `default_backend` or `http-request ... if`), failed to parse it, or misses a
+/// name the tokenizer finds, so a directive is never classified half by each.
+fn grammar_symbol_tokens(tree: &Tree, directive: &Directive) -> Option<Vec<(Token, TokenRole)>> {
+    let span = directive.keyword.span.start..directive.args.last().unwrap_or(&directive.keyword).span.end;
+    let tokenized = directive.symbol_tokens();
+    // The tokenizer's token when it agrees, since it strips the `!` of negated ACLs
+    let token_at = |byte: usize| {
+        tokenized
+            .iter()
+            .map(|(token, _)| token)
+            .chain(std::iter::once(&directive.keyword).chain(&directive.args))
+            .find(|token| token.span.contains(&byte))
+            .cloned()
+    };
+    let condition = match directive.condition() {
+        Some((keyword, _)) if keyword.text == "unless" => ReferenceContext::AclUnlessCondition,
+        _ => ReferenceContext::AclCondition,
+    };
+
+    let mut tokens = Vec::new();
+    // The cursor can't leave the smallest node holding the directive
+    let mut cursor = tree.root_node().descendant_for_byte_range(span.start, span.end)?.walk();
+    loop {
+        let node = cursor.node();
+        let overlaps = node.start_byte() < span.end && span.start < node.end_byte();
+        if overlaps && (node.is_error() || node.is_missing()) {
+            return None;
+        }
+        let parent = node.parent().map(|parent| parent.kind()).unwrap_or("");
+        let role = match node.kind() {
+            _ if !overlaps => None,
+            "section_name" => SectionKind::from_keyword(&directive.keyword.text)
+                .and_then(SectionKind::symbol_kind)
+                .map(TokenRole::Definition),
+            "acl_name" if parent == "acl_directive" => Some(TokenRole::Definition(SymbolKind::Acl)),
+            "acl_name" => Some(TokenRole::Reference(SymbolKind::Acl, condition.clone())),
+            "server_name" if parent == "server_directive" => Some(TokenRole::Definition(SymbolKind::Server)),
+            "backend_ref" if directive.keyword.text == "default_backend" => {
+                Some(TokenRole::Reference(SymbolKind::Backend, ReferenceContext::DefaultBackend))
+            }
+            "backend_ref" => Some(TokenRole::Reference(SymbolKind::Backend, ReferenceContext::UseBackend)),
+            _ => None,
+        };
+        if let Some(role) = role {
+            tokens.extend(token_at(node.start_byte()).map(|token| (token, role)));
+        } else if overlaps && node.kind() != "comment" && cursor.goto_first_child() {
+            continue;
+        }
+
+        loop {
+            if cursor.goto_next_sibling() {
+                break;
+            }
+            if !cursor.goto_parent() {
+                return covering(tokens, &tokenized);
+            }
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-struct Position {
-    line: u32,
-    character: u32,
+/// The grammar's `tokens` for a directive if they include every name the tokenizer
+/// found in it (`tokenized`), or `None` to fall back to the tokenizer.
+fn covering(tokens: Vec<(Token, TokenRole)>, tokenized: &[(Token, TokenRole)]) -> Option<Vec<(Token, TokenRole)>> {
+    let found = |wanted: &Token| tokens.iter().any(|(token, _)| token.span == wanted.span);
+    if tokens.is_empty() || !tokenized.iter().all(|(token, _)| found(token)) {
+        return None;
+    }
+    Some(tokens)
 }
 
-struct HaproxyLsp {
-    parser: Parser,
-    symbols: HashMap<String, Vec<Symbol>>,
+/// The latest text the client sent for a URI and the model parsed from it.
+#[derive(Clone)]
+struct Document {
+    text: String,
+    lines: LineIndex,
+    version: i64,
+    tree: Option<Tree>,
+    config: ConfigFile,
+    /// Release chosen by a `# haproxy-lsp: version=...` comment in the text.
+    haproxy_version: Option<Version>,
 }
 
-impl HaproxyLsp {
-    fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let parser = Parser::new();
-        // TODO: Set up the HAProxy language when tree-sitter integration is ready
-        
-        Ok(HaproxyLsp {
-            parser,
-            symbols: HashMap::new(),
+impl Document {
+    /// The tokens of `directive` that declare or use a name, classified by the grammar
+    /// where it models the directive so navigation agrees with highlighting.
+    fn symbol_tokens(&self, directive: &Directive) -> Vec<(Token, TokenRole)> {
+        self.tree
+            .as_ref()
+            .and_then(|tree| grammar_symbol_tokens(tree, directive))
+            .unwrap_or_else(|| directive.symbol_tokens())
+    }
+}
+
+/// The names one section declares and uses, classified when it was last parsed.
+struct SectionSymbols {
+    /// Header line and byte offset when indexed. An edit above the section only
+    /// moves it, so the tokens are off by how far the header moved since.
+    line: u32,
+    offset: usize,
+    tokens: Vec<(Token, TokenRole)>,
+    /// The backends the section routes to, as in [`CrossFileFacts`].
+    backend_uses: Option<HashSet<String>>,
+}
+
+impl SectionSymbols {
+    fn new(document: &Document, section: &Section) -> Self {
+        let directives = || std::iter::once(&section.header).chain(&section.directives);
+        let symbol_tokens = |directive: &Directive| document.symbol_tokens(directive);
+        SectionSymbols {
+            line: section.header.line(),
+            offset: section.header.keyword.span.start,
+            tokens: directives().flat_map(symbol_tokens).collect(),
+            backend_uses: backend_uses_with(directives(), &symbol_tokens),
+        }
+    }
+
+    /// The tokens `wanted` accepts, moved to where `section`, the one indexed, is now.
+    fn find<'a>(
+        &'a self,
+        section: &Section,
+        wanted: impl Fn(&Token, &TokenRole) -> bool + 'a,
+    ) -> impl Iterator<Item = (Token, &'a TokenRole)> + 'a {
+        let lines = section.header.line() as i64 - self.line as i64;
+        let bytes = section.header.keyword.span.start as i64 - self.offset as i64;
+        self.tokens.iter().filter(move |(token, role)| wanted(token, role)).map(move |(token, role)| {
+            let mut token = token.clone();
+            token.shift(lines, bytes);
+            (token, role)
         })
     }
+}
 
-    fn parse_document(&mut self, uri: &str, content: &str) -> Result<(), Box<dyn std::error::Error>> {
-        // For now, use simple regex-based parsing until tree-sitter integration is complete
-        let mut symbols = Vec::new();
-        
-        for (line_num, line) in content.lines().enumerate() {
-            let line = line.trim();
-            
-            // Parse backend definitions
-            if line.starts_with("backend ") {
-                let name = line.strip_prefix("backend ").unwrap_or("").trim();
-                if !name.is_empty() {
-                    symbols.push(Symbol {
-                        name: name.to_string(),
-                        kind: SymbolKind::Backend,
-                        range: Range {
-                            start: Position { line: line_num as u32, character: 0 },
-                            end: Position { line: line_num as u32, character: line.len() as u32 },
-                        },
-                        uri: uri.to_string(),
-                        references: Vec::new(),
-                    });
-                }
-            }
-            // Parse frontend definitions
-            else if line.starts_with("frontend ") {
-                let name = line.strip_prefix("frontend ").unwrap_or("").trim();
-                if !name.is_empty() {
-                    symbols.push(Symbol {
-                        name: name.to_string(),
-                        kind: SymbolKind::Frontend,
-                        range: Range {
-                            start: Position { line: line_num as u32, character: 0 },
-                            end: Position { line: line_num as u32, character: line.len() as u32 },
-                        },
-                        uri: uri.to_string(),
-                        references: Vec::new(),
-                    });
-                }
-            }
-            // Parse listen definitions
-            else if line.starts_with("listen ") {
-                let name = line.strip_prefix("listen ").unwrap_or("").trim();
-                if !name.is_empty() {
-                    symbols.push(Symbol {
-                        name: name.to_string(),
-                        kind: SymbolKind::Listen,
-                        range: Range {
-                            start: Position { line: line_num as u32, character: 0 },
-                            end: Position { line: line_num as u32, character: line.len() as u32 },
-                        },
-                        uri: uri.to_string(),
-                        references: Vec::new(),
-                    });
-                }
-            }
-            // Parse ACL definitions
-            else if line.starts_with("acl ") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let name = parts[1];
-                    symbols.push(Symbol {
-                        name: name.to_string(),
-                        kind: SymbolKind::Acl,
-                        range: Range {
-                            start: Position { line: line_num as u32, character: 0 },
-                            end: Position { line: line_num as u32, character: line.len() as u32 },
-                        },
-                        uri: uri.to_string(),
-                        references: Vec::new(),
-                    });
-                }
-            }
-            // Parse server definitions
-            else if line.trim_start().starts_with("server ") {
-                let parts: Vec<&str> = line.trim_start().split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let name = parts[1];
-                    symbols.push(Symbol {
-                        name: name.to_string(),
-                        kind: SymbolKind::Server,
-                        range: Range {
-                            start: Position { line: line_num as u32, character: 0 },
-                            end: Position { line: line_num as u32, character: line.len() as u32 },
-                        },
-                        uri: uri.to_string(),
-                        references: Vec::new(),
-                    });
-                }
+/// The proxies a document declares with their header ranges, and the backends it
+/// uses (`None` when a dynamic target may pick any).
+type CrossFileFacts = (Vec<(SectionKind, String, Range)>, Option<HashSet<String>>);
+
+/// What the diagnostics of other files read from `document`, whose sections are
+/// indexed in `symbols`. While this stays the same, an edit to `document` only
+/// changes its own diagnostics.
+fn cross_file_facts(document: &Document, symbols: &[Arc<SectionSymbols>]) -> CrossFileFacts {
+    let proxies = document
+        .config
+        .sections
+        .iter()
+        .filter(|section| section.kind.symbol_kind().is_some())
+        .filter_map(|section| Some((section.kind, section.name()?.text.clone(), section.header.range())))
+        .collect();
+    let mut uses = backend_uses_with(&document.config.preamble, &|directive| document.symbol_tokens(directive));
+    for section in symbols {
+        uses = uses.zip(section.backend_uses.as_ref()).map(|(mut uses, more)| {
+            uses.extend(more.iter().cloned());
+            uses
+        });
+    }
+    (proxies, uses)
+}
+
+/// The documents and symbol index requests are answered from. Cloning it is cheap,
+/// so background requests work on a snapshot while edits keep coming in.
+#[derive(Clone, Default)]
+struct Workspace {
+    documents: HashMap<String, Arc<Document>>,
+    /// Open URIs in the order they were opened, which for `check` is the `-f` order
+    /// HAProxy loads them in.
+    load_order: Vec<String>,
+    /// The symbol tokens of each document, by section.
+    symbols: HashMap<String, Arc<Vec<Arc<SectionSymbols>>>>,
+    /// What the diagnostics of the other open files read from each document.
+    facts: HashMap<String, Arc<CrossFileFacts>>,
+    encoding: PositionEncoding,
+    /// Release set by the `haproxyVersion` initialization option.
+    haproxy_version: Option<Version>,
+}
+
+impl Workspace {
+    /// Converts a client position in `uri` to the byte column the model uses.
+    fn position_from_client(&self, uri: &str, position: Position) -> Position {
+        match self.documents.get(uri) {
+            Some(document) if self.encoding != PositionEncoding::Utf8 => {
+                let line = document.lines.line(&document.text, position.line);
+                Position { character: self.encoding.byte_column(line, position.character), ..position }
             }
+            _ => position,
         }
-        
-        // Second pass: collect references to symbols
-        let mut updated_symbols = symbols;
-        for (line_num, line) in content.lines().enumerate() {
-            let line = line.trim();
-            
-            // Collect backend references
-            if line.contains("use_backend") {
-                if let Some(backend_name) = self.extract_backend_from_use_backend(line) {
-                    self.add_reference_to_symbol(&mut updated_symbols, &backend_name, SymbolKind::Backend, 
-                                              Reference {
-                                                  range: Range {
-                                                      start: Position { line: line_num as u32, character: 0 },
-                                                      end: Position { line: line_num as u32, character: line.len() as u32 },
-                                                  },
-                                                  uri: uri.to_string(),
-                                                  context: ReferenceContext::UseBackend,
-                                              });
-                }
-            }
-            
-            if line.contains("default_backend") {
-                if let Some(backend_name) = self.extract_backend_from_default_backend(line) {
-                    self.add_reference_to_symbol(&mut updated_symbols, &backend_name, SymbolKind::Backend,
-                                              Reference {
-                                                  range: Range {
-                                                      start: Position { line: line_num as u32, character: 0 },
-                                                      end: Position { line: line_num as u32, character: line.len() as u32 },
-                                                  },
-                                                  uri: uri.to_string(),
-                                                  context: ReferenceContext::DefaultBackend,
-                                              });
-                }
+    }
+
+    /// Converts a model range in `uri` to the client's position encoding.
+    fn range_to_client(&self, uri: &str, range: &Range) -> Range {
+        let document = match self.documents.get(uri) {
+            Some(document) if self.encoding != PositionEncoding::Utf8 => document,
+            _ => return range.clone(),
+        };
+        let convert = |position: &Position| Position {
+            line: position.line,
+            character: self.encoding.column(document.lines.line(&document.text, position.line), position.character),
+        };
+        Range { start: convert(&range.start), end: convert(&range.end) }
+    }
+
+    /// The open documents in load order.
+    fn documents_in_load_order(&self) -> impl Iterator<Item = (&str, &Document)> {
+        self.load_order.iter().filter_map(|uri| Some((uri.as_str(), &**self.documents.get(uri)?)))
+    }
+
+    /// The HAProxy release `uri` is checked against: its own pragma, else the
+    /// workspace setting. Without either, no release-specific checks apply.
+    fn target_version(&self, uri: &str) -> Option<Version> {
+        self.documents.get(uri).and_then(|document| document.haproxy_version).or(self.haproxy_version)
+    }
+
+    /// Rebuilds the symbols of the sections of `uri` that `reparsed` reports, or of
+    /// all of them, and its cross-file facts, from its stored document.
+    fn index(&mut self, uri: &str, reparsed: Option<Reparsed>) {
+        let document = match self.documents.get(uri) {
+            Some(document) => document,
+            None => return,
+        };
+        let sections = &document.config.sections;
+        let indexed = |range: std::ops::Range<usize>| {
+            sections[range].iter().map(|section| Arc::new(SectionSymbols::new(document, section)))
+        };
+
+        let symbols = self.symbols.entry(uri.to_string()).or_default();
+        match reparsed {
+            Some(reparsed) => {
+                Arc::make_mut(symbols).splice(reparsed.old, indexed(reparsed.new));
             }
-            
-            // Collect ACL references
-            if line.contains(" if ") {
-                if let Some(acl_names) = self.extract_acl_names_from_condition(line, "if") {
-                    for acl_name in acl_names {
-                        self.add_reference_to_symbol(&mut updated_symbols, &acl_name, SymbolKind::Acl,
-                                                  Reference {
-                                                      range: Range {
-                                                          start: Position { line: line_num as u32, character: 0 },
-                                                          end: Position { line: line_num as u32, character: line.len() as u32 },
-                                                      },
-                                                      uri: uri.to_string(),
-                                                      context: ReferenceContext::AclCondition,
-                                                  });
+            None => *symbols = Arc::new(indexed(0..sections.len()).collect()),
+        }
+        self.facts.insert(uri.to_string(), Arc::new(cross_file_facts(document, symbols)));
+    }
+
+    /// The symbol whose name is under the cursor, whether it is declared or used there.
+    fn symbol_at(&self, uri: &str, position: &Position) -> Option<Symbol> {
+        let document = self.documents.get(uri)?;
+        let symbol_tokens = |directive: &Directive| document.symbol_tokens(directive);
+        let (token, kind, scopes) = symbol_token_at_with(&document.config, position, &symbol_tokens)?;
+
+        if kind.is_section_local() {
+            self.resolve_local(uri, &token.text, &kind, &scopes)
+        } else {
+            self.find_symbol_by_name(&token.text, kind)
+        }
+    }
+
+    /// The declaration in `uri` a section-local `name` resolves to, searching the
+    /// sections in `scopes` in order.
+    fn resolve_local(&self, uri: &str, name: &str, kind: &SymbolKind, scopes: &[usize]) -> Option<Symbol> {
+        let (document, symbols) = (self.documents.get(uri)?, self.symbols.get(uri)?);
+        scopes.iter().find_map(|&scope| {
+            let declares = |token: &Token, role: &TokenRole| {
+                token.text == name && matches!(role, TokenRole::Definition(declared) if declared == kind)
+            };
+            let (token, _) = symbols.get(scope)?.find(document.config.sections.get(scope)?, declares).next()?;
+            Some(Symbol {
+                name: token.text,
+                kind: kind.clone(),
+                range: token.range,
+                uri: uri.to_string(),
+                section: Some(scope),
+                references: Vec::new(),
+            })
+        })
+    }
+
+    /// The problems found in `uri`, as published to the client and reported by `check`.
+    fn diagnostics(&self, uri: &str) -> Vec<Diagnostic> {
+        let document = match self.documents.get(uri) {
+            Some(document) => document,
+            None => return Vec::new(),
+        };
+        // In load order, so the "first definition" of a duplicate is the one HAProxy keeps
+        let configs: Vec<(&str, &ConfigFile)> =
+            self.documents_in_load_order().map(|(uri, document)| (uri, &document.config)).collect();
+        // Classified like navigation classifies them, grammar first
+        let symbol_tokens = |uri: &str, directive: &Directive| match self.documents.get(uri) {
+            Some(document) => document.symbol_tokens(directive),
+            None => directive.symbol_tokens(),
+        };
+        diagnose_with(uri, &document.config, &configs, self.target_version(uri), &symbol_tokens)
+    }
+
+    /// Keywords to offer at `position`: section headers and the keywords the
+    /// enclosing section accepts at the start of a line, or the second word of
+    /// two-word keywords like `option httpchk` after the first.
+    fn completions(&self, uri: &str, position: &Position) -> Vec<Value> {
+        let document = match self.documents.get(uri) {
+            Some(document) => document,
+            None => return Vec::new(),
+        };
+        let line = document.lines.line(&document.text, position.line);
+        let typed = line.get(..position.character as usize).unwrap_or(line).trim_start();
+        if typed.contains('#') {
+            return Vec::new();
+        }
+
+        let version = self.target_version(uri);
+        let available = |keyword: &Keyword| match version {
+            Some(version) => keyword.available_in(version),
+            None => keyword.removed.is_none(),
+        };
+        let kind = document.config.section_at(position.line).map(|section| section.kind);
+        let keywords = KEYWORDS
+            .iter()
+            .filter(|keyword| kind.is_some_and(|kind| keyword.allowed_in(kind)) && available(keyword))
+            .filter(|keyword| !keyword.name.ends_with('*'));
+
+        let mut words: Vec<&str> = typed.split_whitespace().collect();
+        if words.first() == Some(&"no") {
+            words.remove(0);
+        }
+        let mut items = Vec::new();
+        match (words.as_slice(), typed.ends_with(char::is_whitespace)) {
+            ([] | [_], false) => {
+                let prefix = words.first().copied().unwrap_or("");
+                for kind in SectionKind::ALL {
+                    if kind.keyword().starts_with(prefix) && version.is_none_or(|version| section_since(kind) <= version) {
+                        items.push(json!({ "label": kind.keyword(), "kind": 9, "detail": "section" }));
                     }
                 }
+                let mut labels: Vec<&str> = keywords
+                    .map(|keyword| keyword.name.split(' ').next().unwrap_or(keyword.name))
+                    .filter(|label| label.starts_with(prefix))
+                    .collect();
+                labels.sort();
+                labels.dedup();
+                items.extend(labels.into_iter().map(|label| json!({ "label": label, "kind": 14 })));
             }
-            
-            if line.contains(" unless ") {
-                if let Some(acl_names) = self.extract_acl_names_from_condition(line, "unless") {
-                    for acl_name in acl_names {
-                        self.add_reference_to_symbol(&mut updated_symbols, &acl_name, SymbolKind::Acl,
-                                                  Reference {
-                                                      range: Range {
-                                                          start: Position { line: line_num as u32, character: 0 },
-                                                          end: Position { line: line_num as u32, character: line.len() as u32 },
-                                                      },
-                                                      uri: uri.to_string(),
-                                                      context: ReferenceContext::AclUnlessCondition,
-                                                  });
+            ([first], true) | ([first, _], false) => {
+                let prefix = words.get(1).copied().unwrap_or("");
+                for keyword in keywords {
+                    if let Some(second) = keyword.name.strip_prefix(*first).and_then(|rest| rest.strip_prefix(' ')) {
+                        if second.starts_with(prefix) {
+                            items.push(json!({
+                                "label": second,
+                                "kind": 14,
+                                "documentation": { "kind": "markdown", "value": keyword_summary(keyword) }
+                            }));
+                        }
                     }
                 }
             }
+            _ => {}
         }
-        
-        self.symbols.insert(uri.to_string(), updated_symbols);
-        Ok(())
+        items
     }
 
-    fn find_definition(&self, _uri: &str, position: &Position, content: &str) -> Option<Symbol> {
-        // Find what symbol is at the given position using simple text parsing
-        let lines: Vec<&str> = content.lines().collect();
-        if position.line as usize >= lines.len() {
-            return None;
+    /// Quick fixes rewriting the removed keywords on the lines of `range` into their
+    /// modern equivalents, plus one fixing the whole file when it has several.
+    /// `diagnostics` are the client's diagnostics for the range, linked to the fixes.
+    fn code_actions(&self, uri: &str, range: &Range, diagnostics: &[Value]) -> Vec<Value> {
+        let document = match self.documents.get(uri) {
+            Some(document) => document,
+            None => return Vec::new(),
+        };
+        let edit = |directive: &Directive, new_text: String| {
+            json!({ "range": range_json(&self.range_to_client(uri, &directive.range())), "newText": new_text })
+        };
+        let migrations: Vec<_> = document
+            .config
+            .directives()
+            .filter_map(|directive| Some((directive, migrate(directive, &document.text)?)))
+            .collect();
+
+        let mut actions = Vec::new();
+        for (directive, new_text) in &migrations {
+            let line = directive.line();
+            if line < range.start.line || range.end.line < line {
+                continue;
+            }
+            let replacement = directive.keyword_entry().and_then(|(keyword, _)| keyword.replaced_by);
+            let linked: Vec<&Value> = diagnostics
+                .iter()
+                .filter(|diagnostic| {
+                    matches!(diagnostic["code"].as_str(), Some("deprecated-keyword" | "unsupported-keyword"))
+                        && diagnostic["range"]["start"]["line"].as_u64() == Some(line as u64)
+                })
+                .collect();
+            actions.push(json!({
+                "title": format!("Replace with `{}`", replacement.unwrap_or(new_text)),
+                "kind": "quickfix",
+                "diagnostics": linked,
+                "isPreferred": true,
+                "edit": { "changes": { uri: [edit(directive, new_text.clone())] } }
+            }));
         }
-        
-        let line = lines[position.line as usize];
-        let char_pos = position.character as usize;
-        
-        // Find word boundaries around the cursor position
-        let word = self.get_word_at_position(line, char_pos)?;
-        
-        // Check if this line contains a reference pattern
-        if line.contains("use_backend") {
-            // Extract the backend name from "use_backend BACKEND_NAME"
-            if let Some(backend_name) = self.extract_backend_from_use_backend(line) {
-                return self.find_symbol_by_name(&backend_name, SymbolKind::Backend);
-            }
-        } else if line.contains("default_backend") {
-            // Extract the backend name from "default_backend BACKEND_NAME"
-            if let Some(backend_name) = self.extract_backend_from_default_backend(line) {
-                return self.find_symbol_by_name(&backend_name, SymbolKind::Backend);
-            }
-        } else if line.contains(" if ") && !word.is_empty() {
-            // Check if this might be an ACL reference in a condition
-            return self.find_symbol_by_name(&word, SymbolKind::Acl);
-        }
-        
-        None
-    }
-    
-    fn extract_backend_from_use_backend(&self, line: &str) -> Option<String> {
-        // Parse "use_backend BACKEND_NAME [if condition]"
-        let parts: Vec<&str> = line.trim().split_whitespace().collect();
-        if parts.len() >= 2 && parts[0] == "use_backend" {
-            Some(parts[1].to_string())
-        } else {
-            None
+
+        if !actions.is_empty() && migrations.len() > 1 {
+            let edits: Vec<Value> =
+                migrations.iter().map(|(directive, new_text)| edit(directive, new_text.clone())).collect();
+            actions.push(json!({
+                "title": format!("Rewrite all {} removed keywords in this file", migrations.len()),
+                "kind": "quickfix",
+                "edit": { "changes": { uri: edits } }
+            }));
         }
+        actions
     }
-    
-    fn extract_backend_from_default_backend(&self, line: &str) -> Option<String> {
-        // Parse "default_backend BACKEND_NAME"
-        let parts: Vec<&str> = line.trim().split_whitespace().collect();
-        if parts.len() >= 2 && parts[0] == "default_backend" {
-            Some(parts[1].to_string())
-        } else {
-            None
+
+    /// Markdown describing what is under the cursor: the family and port of an
+    /// address, or where a section or keyword may appear and which releases accept it.
+    fn hover(&self, uri: &str, position: &Position) -> Option<Value> {
+        let document = self.documents.get(uri)?;
+        let directive = document.config.directive_at(position.line)?;
+        let version = self.target_version(uri);
+
+        if let Some((token, role)) = directive.address_token().filter(|(token, _)| token.contains(position)) {
+            let lines: Vec<String> = token
+                .text
+                .split(',')
+                .map(|address| match parse_address(address, role) {
+                    Ok(parsed) => format!("`{}`: {}", address, parsed.describe()),
+                    Err(err) => format!("`{}`: {}", address, err),
+                })
+                .collect();
+            return Some(json!({
+                "contents": { "kind": "markdown", "value": lines.join("\n\n") },
+                "range": range_json(&self.range_to_client(uri, &token.range))
+            }));
+        }
+
+        let (range, mut markdown, supported) = match SectionKind::from_keyword(&directive.keyword.text) {
+            Some(kind) => {
+                let since = section_since(kind);
+                let markdown = format!("**`{}`** section\n\nSince HAProxy {}", kind.keyword(), since);
+                (directive.keyword.range.clone(), markdown, version.is_none_or(|version| since <= version))
+            }
+            None => {
+                let (keyword, range) = directive.keyword_entry()?;
+                let markdown = format!("**`{}`**\n\n{}", keyword.name, keyword_summary(keyword));
+                (range, markdown, version.is_none_or(|version| keyword.available_in(version)))
+            }
+        };
+        let (start, end) = (&range.start, &range.end);
+        let inside = (start.line, start.character) <= (position.line, position.character)
+            && (position.line, position.character) <= (end.line, end.character);
+        if !inside {
+            return None;
         }
+
+        if let (Some(version), false) = (version, supported) {
+            markdown.push_str(&format!("\n\n**Not available in HAProxy {}**, the targeted release", version));
+        }
+        Some(json!({
+            "contents": { "kind": "markdown", "value": markdown },
+            "range": range_json(&self.range_to_client(uri, &range))
+        }))
     }
-    
-    fn add_reference_to_symbol(&self, symbols: &mut Vec<Symbol>, symbol_name: &str, symbol_kind: SymbolKind, reference: Reference) {
-        for symbol in symbols.iter_mut() {
-            if symbol.name == symbol_name && std::mem::discriminant(&symbol.kind) == std::mem::discriminant(&symbol_kind) {
-                symbol.references.push(reference);
-                break;
+
+    /// Every use of the symbol under the cursor, optionally preceded by its declaration.
+    /// ACLs and servers are only used in their own file; proxies in any open one.
+    fn find_references(&self, uri: &str, position: &Position, include_declaration: bool) -> Option<Vec<(String, Range)>> {
+        let symbol = self.symbol_at(uri, position)?;
+
+        let mut locations = Vec::new();
+        if include_declaration {
+            locations.push((symbol.uri.clone(), symbol.range.clone()));
+        }
+        if symbol.kind.is_section_local() {
+            locations.extend(self.local_references(&symbol));
+            return Some(locations);
+        }
+
+        // A file's own index only resolves the proxies it declares, so scan them all
+        for (uri, document) in self.documents_in_load_order() {
+            for directive in document.config.directives() {
+                for (token, role) in document.symbol_tokens(directive) {
+                    match role {
+                        TokenRole::Reference(kind, _) if token.text == symbol.name && symbol.kind.satisfies(&kind) => {
+                            locations.push((uri.to_string(), token.range));
+                        }
+                        _ => {}
+                    }
+                }
             }
         }
+        Some(locations)
     }
-    
-    fn extract_acl_names_from_condition(&self, line: &str, condition_type: &str) -> Option<Vec<String>> {
-        // Find the condition part after "if" or "unless"
-        let condition_start = line.find(&format!(" {} ", condition_type))?;
-        let condition_part = &line[condition_start + condition_type.len() + 2..];
-        
-        // Simple parsing: split by whitespace and filter out operators and logical keywords
-        let parts: Vec<&str> = condition_part.split_whitespace().collect();
-        let mut acl_names = Vec::new();
-        
-        for part in parts {
-            // Skip HAProxy operators and keywords
-            if part == "||" || part == "&&" || part == "!" || part.starts_with('!') || part == "{" {
+
+    /// Uses of a section-local `symbol` that resolve to it: in its own section and,
+    /// for an ACL of a defaults section, in the sections inheriting it.
+    fn local_references(&self, symbol: &Symbol) -> Vec<(String, Range)> {
+        let (document, symbols) = match (self.documents.get(&symbol.uri), self.symbols.get(&symbol.uri)) {
+            (Some(document), Some(symbols)) => (document, symbols),
+            _ => return Vec::new(),
+        };
+        let sections = &document.config.sections;
+        let home = match symbol.section.filter(|&home| home < sections.len()) {
+            Some(home) => home,
+            None => return Vec::new(),
+        };
+        let inherited = symbol.kind == SymbolKind::Acl && sections[home].kind == SectionKind::Defaults;
+        let candidates = if inherited { home..sections.len() } else { home..home + 1 };
+
+        let mut locations = Vec::new();
+        for index in candidates {
+            let scopes = document.config.lookup_scopes(Some(index), &symbol.kind);
+            if !scopes.contains(&home) {
                 continue;
             }
-            // Stop at opening brace or other control characters
-            if part.contains('{') {
-                break;
-            }
-            // Remove negation prefix and add ACL name
-            let clean_name = part.trim_start_matches('!').trim();
-            if !clean_name.is_empty() && clean_name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
-                acl_names.push(clean_name.to_string());
+            let uses = |token: &Token, role: &TokenRole| {
+                token.text == symbol.name && matches!(role, TokenRole::Reference(used, _) if *used == symbol.kind)
+            };
+            for (token, _) in symbols[index].find(&sections[index], uses) {
+                // A declaration closer to the use shadows the symbol
+                let resolved = self.resolve_local(&symbol.uri, &token.text, &symbol.kind, &scopes);
+                if resolved.and_then(|resolved| resolved.section) == Some(home) {
+                    locations.push((symbol.uri.clone(), token.range));
+                }
             }
         }
-        
-        if acl_names.is_empty() {
-            None
-        } else {
-            Some(acl_names)
-        }
+        locations
     }
-    
-    fn get_word_at_position(&self, line: &str, char_pos: usize) -> Option<String> {
-        if char_pos >= line.len() {
-            return None;
-        }
-        
-        // Find word boundaries
-        let chars: Vec<char> = line.chars().collect();
-        
-        // If we're on whitespace, try to find the next word
-        let mut pos = char_pos;
-        if pos < chars.len() && chars[pos].is_whitespace() {
-            while pos < chars.len() && chars[pos].is_whitespace() {
-                pos += 1;
+
+    /// The first declaration of a proxy in load order; proxies are visible in every file.
+    fn find_symbol_by_name(&self, name: &str, kind: SymbolKind) -> Option<Symbol> {
+        let declares = |token: &Token, role: &TokenRole| {
+            token.text == name && matches!(role, TokenRole::Definition(declared) if declared.satisfies(&kind))
+        };
+        self.documents_in_load_order().find_map(|(uri, document)| {
+            let symbols = self.symbols.get(uri)?;
+            symbols.iter().zip(&document.config.sections).find_map(|(symbols, section)| {
+                let (token, role) = symbols.find(section, declares).next()?;
+                let kind = match role {
+                    TokenRole::Definition(kind) => kind.clone(),
+                    TokenRole::Reference(kind, _) => kind.clone(),
+                };
+                Some(Symbol {
+                    name: token.text,
+                    kind,
+                    range: token.range,
+                    uri: uri.to_string(),
+                    section: None,
+                    references: Vec::new(),
+                })
+            })
+        })
+    }
+
+    /// Answers the requests that only read the workspace.
+    fn handle_query(&self, method: &str, params: &Value) -> Result<Value, ResponseError> {
+        match method {
+            "textDocument/definition" | "textDocument/declaration" => {
+                let uri = str_param(params, "/textDocument/uri")?;
+                let position = self.position_from_client(uri, position_param(params, "/position")?);
+
+                // HAProxy names are declared where they are defined, so both requests
+                // resolve to the same location
+                let symbol = self.symbol_at(uri, &position);
+                Ok(symbol
+                    .map(|symbol| location_json(&symbol.uri, &self.range_to_client(&symbol.uri, &symbol.range)))
+                    .unwrap_or(Value::Null))
             }
+            "textDocument/references" => {
+                let uri = str_param(params, "/textDocument/uri")?;
+                let position = self.position_from_client(uri, position_param(params, "/position")?);
+                let include_declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(false);
+
+                let locations: Vec<Value> = self
+                    .find_references(uri, &position, include_declaration)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|(uri, range)| location_json(uri, &self.range_to_client(uri, range)))
+                    .collect();
+                Ok(json!(locations))
+            }
+            "textDocument/completion" => {
+                let uri = str_param(params, "/textDocument/uri")?;
+                let position = self.position_from_client(uri, position_param(params, "/position")?);
+                Ok(json!(self.completions(uri, &position)))
+            }
+            "textDocument/codeAction" => {
+                let uri = str_param(params, "/textDocument/uri")?;
+                let range = parse_range(&params["range"]).ok_or_else(|| ResponseError::invalid_params("/range"))?;
+                let range = Range {
+                    start: self.position_from_client(uri, range.start),
+                    end: self.position_from_client(uri, range.end),
+                };
+                let diagnostics = params["context"]["diagnostics"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+                Ok(json!(self.code_actions(uri, &range, diagnostics)))
+            }
+            "textDocument/hover" => {
+                let uri = str_param(params, "/textDocument/uri")?;
+                let position = self.position_from_client(uri, position_param(params, "/position")?);
+                Ok(self.hover(uri, &position).unwrap_or(Value::Null))
+            }
+            // Stand-ins for a slow and a broken query in the tests
+            #[cfg(test)]
+            "test/sleep" => {
+                thread::sleep(Duration::from_millis(params.as_u64().unwrap_or(0)));
+                Ok(params.clone())
+            }
+            #[cfg(test)]
+            "test/panic" => panic!("test/panic"),
+            _ => Err(ResponseError::new(
+                ErrorCode::MethodNotFound,
+                format!("method not found: {}", method),
+            )),
         }
-        
-        if pos >= chars.len() {
-            return None;
-        }
-        
-        // Now find word boundaries from this position
-        let mut start = pos;
-        let mut end = pos;
-        
-        // Find start of word
-        while start > 0 && (chars[start - 1].is_alphanumeric() || chars[start - 1] == '_' || chars[start - 1] == '-') {
-            start -= 1;
-        }
-        
-        // Find end of word
-        while end < chars.len() && (chars[end].is_alphanumeric() || chars[end] == '_' || chars[end] == '-') {
-            end += 1;
-        }
-        
-        if start < end {
-            Some(chars[start..end].iter().collect())
+    }
+}
+
+/// Requests answered from a workspace snapshot on the worker pool, so that a slow
+/// one never holds up the edits behind it.
+const BACKGROUND_METHODS: &[&str] = &[
+    "textDocument/definition",
+    "textDocument/declaration",
+    "textDocument/references",
+    "textDocument/completion",
+    "textDocument/codeAction",
+    "textDocument/hover",
+];
+
+struct HaproxyLsp {
+    parser: Parser,
+    has_grammar: bool,
+    /// Why the grammar isn't used, for the server to log; `check` output stays clean.
+    grammar_notice: Option<String>,
+    workspace: Workspace,
+    shutdown_requested: bool,
+    /// Set once `exit` arrives: 0 after a clean shutdown, 1 otherwise.
+    exit_code: Option<i32>,
+    /// Where to queue documents whose diagnostics need publishing, once serving.
+    diagnostics: Option<DiagnosticsQueue>,
+}
+
+impl HaproxyLsp {
+    fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let mut parser = Parser::new();
+        let grammar_notice = match haproxy_language() {
+            Some(language) => match parser.set_language(language) {
+                Ok(()) => None,
+                Err(err) => Some(format!("incompatible tree-sitter-haproxy grammar ({}), using line-based parsing", err)),
+            },
+            None => Some("built without tree-sitter-haproxy grammar, using line-based parsing".to_string()),
+        };
+
+        Ok(HaproxyLsp {
+            parser,
+            has_grammar: grammar_notice.is_none(),
+            grammar_notice,
+            workspace: Workspace::default(),
+            shutdown_requested: false,
+            exit_code: None,
+            diagnostics: None,
+        })
+    }
+
+    /// Stores `text` as the current content of `uri` and re-indexes it.
+    fn parse_document(&mut self, uri: &str, text: String, version: i64) -> Result<(), Box<dyn std::error::Error>> {
+        let tree = if self.has_grammar {
+            self.parser.parse(&text, None)
         } else {
             None
+        };
+        let config = ConfigFile::parse(&text);
+        let lines = LineIndex::new(&text);
+        let haproxy_version = version_pragma(&text);
+        let document = Document { text, lines, version, tree, config, haproxy_version };
+
+        if self.workspace.documents.insert(uri.to_string(), Arc::new(document)).is_none() {
+            self.workspace.load_order.push(uri.to_string());
         }
+        self.workspace.index(uri, None);
+        Ok(())
     }
 
-    fn find_symbol_by_name(&self, name: &str, kind: SymbolKind) -> Option<Symbol> {
-        for symbols in self.symbols.values() {
-            for symbol in symbols {
-                if symbol.name == name && std::mem::discriminant(&symbol.kind) == std::mem::discriminant(&kind) {
-                    return Some(symbol.clone());
-                }
-            }
+    /// Applies one incremental change to a stored document, letting tree-sitter
+    /// reuse the unchanged parts of the old tree and re-parsing only the touched sections.
+    fn edit_document(&mut self, uri: &str, range: &Range, new_text: &str, version: i64) -> Result<(), Box<dyn std::error::Error>> {
+        let document = self.workspace.documents.get_mut(uri).ok_or("change to a document that is not open")?;
+        // Copies the document only if a background request still holds the old one
+        let document = Arc::make_mut(document);
+
+        let start_byte = document.lines.offset(&document.text, &range.start);
+        let old_end_byte = document.lines.offset(&document.text, &range.end).max(start_byte);
+        let start_position = document.lines.position(start_byte);
+        let old_end_position = document.lines.position(old_end_byte);
+        // Only a comment can carry the version pragma
+        let touched = start_position.line..=old_end_position.line;
+        let touches_comment =
+            new_text.contains('#') || touched.into_iter().any(|line| document.lines.line(&document.text, line).contains('#'));
+
+        document.text.replace_range(start_byte..old_end_byte, new_text);
+        let new_end_byte = start_byte + new_text.len();
+        let new_end_position = match new_text.rfind('\n') {
+            Some(newline) => Position {
+                line: start_position.line + new_text.matches('\n').count() as u32,
+                character: (new_text.len() - newline - 1) as u32,
+            },
+            None => Position { line: start_position.line, character: start_position.character + new_text.len() as u32 },
+        };
+        let edit = Edit {
+            start_byte,
+            old_end_byte,
+            new_end_byte,
+            start_line: start_position.line,
+            old_end_line: old_end_position.line,
+            new_end_line: new_end_position.line,
+        };
+        document.lines.edit(&document.text, &edit);
+
+        if let Some(tree) = document.tree.as_mut() {
+            tree.edit(&InputEdit {
+                start_byte,
+                old_end_byte,
+                new_end_byte,
+                start_position: point(start_position),
+                old_end_position: point(old_end_position),
+                new_end_position: point(new_end_position),
+            });
+            document.tree = self.parser.parse(&document.text, Some(tree));
         }
-        None
-    }
 
-    fn find_declaration(&self, uri: &str, position: &Position, content: &str) -> Option<Vec<Reference>> {
-        // Find what symbol is at the given position
-        let lines: Vec<&str> = content.lines().collect();
-        if position.line as usize >= lines.len() {
-            return None;
+        let reparsed = document.config.reparse(&document.text, &edit);
+        document.version = version;
+        if touches_comment {
+            document.haproxy_version = version_pragma(&document.text);
         }
-        
-        let line = lines[position.line as usize];
-        
-        // Check if we're on a symbol definition (backend, acl, etc.)
-        // If so, return all references to that symbol
-        
-        // Check if this line defines a backend
-        if line.trim().starts_with("backend ") {
-            let name = line.trim().strip_prefix("backend ").unwrap_or("").trim();
-            if !name.is_empty() {
-                return self.find_references_to_symbol(name, SymbolKind::Backend);
-            }
-        }
-        
-        // Check if this line defines an ACL
-        if line.trim().starts_with("acl ") {
-            let parts: Vec<&str> = line.trim().split_whitespace().collect();
-            if parts.len() >= 2 {
-                let name = parts[1];
-                return self.find_references_to_symbol(name, SymbolKind::Acl);
-            }
-        }
-        
-        // Check if this line defines a frontend
-        if line.trim().starts_with("frontend ") {
-            let name = line.trim().strip_prefix("frontend ").unwrap_or("").trim();
-            if !name.is_empty() {
-                return self.find_references_to_symbol(name, SymbolKind::Frontend);
-            }
-        }
-        
-        // Check if this line defines a listen section
-        if line.trim().starts_with("listen ") {
-            let name = line.trim().strip_prefix("listen ").unwrap_or("").trim();
-            if !name.is_empty() {
-                return self.find_references_to_symbol(name, SymbolKind::Listen);
-            }
-        }
-        
-        // Check if this line defines a server
-        if line.trim().trim_start().starts_with("server ") {
-            let parts: Vec<&str> = line.trim().trim_start().split_whitespace().collect();
-            if parts.len() >= 2 {
-                let name = parts[1];
-                return self.find_references_to_symbol(name, SymbolKind::Server);
-            }
-        }
-        
-        None
+
+        self.workspace.index(uri, reparsed);
+        Ok(())
     }
-    
-    fn find_references_to_symbol(&self, symbol_name: &str, symbol_kind: SymbolKind) -> Option<Vec<Reference>> {
-        for symbols in self.symbols.values() {
-            for symbol in symbols {
-                if symbol.name == symbol_name && std::mem::discriminant(&symbol.kind) == std::mem::discriminant(&symbol_kind) {
-                    if symbol.references.is_empty() {
-                        return None;
-                    } else {
-                        return Some(symbol.references.clone());
-                    }
+
+    fn close_document(&mut self, uri: &str) {
+        self.workspace.documents.remove(uri);
+        self.workspace.load_order.retain(|open| open != uri);
+        self.workspace.symbols.remove(uri);
+        self.workspace.facts.remove(uri);
+    }
+
+    /// Handles one incoming JSON-RPC message and returns the response to send, if any.
+    /// Requests always get a response; notifications never do.
+    fn handle_request(&mut self, request: Value) -> Option<Value> {
+        let id = request.get("id").cloned();
+        let method = match request["method"].as_str() {
+            Some(method) => method,
+            // The server sends no requests, so a response can only be stray; it gets none
+            None if request.get("result").is_some() || request.get("error").is_some() => return None,
+            None => {
+                return id.map(|id| error_response(&id, ResponseError::new(ErrorCode::InvalidRequest, "missing method")));
+            }
+        };
+        let params = &request["params"];
+
+        match id {
+            Some(id) => {
+                // A panicking handler must not leave the client waiting forever
+                let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| self.handle_call(method, params)))
+                    .unwrap_or_else(|_| Err(ResponseError::new(ErrorCode::InternalError, format!("{} failed", method))));
+
+                Some(match result {
+                    Ok(result) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "result": result
+                    }),
+                    Err(err) => error_response(&id, err),
+                })
+            }
+            None => {
+                // Nobody waits for a notification, but a panic must not take the server down
+                let notify = || self.handle_notification(method, params);
+                let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(notify))
+                    .unwrap_or_else(|_| Err(ResponseError::new(ErrorCode::InternalError, "handler panicked")));
+                if let Err(err) = result {
+                    eprintln!("HAProxy LSP: {}: {}", method, err.message);
                 }
+                None
             }
         }
-        None
     }
 
-    fn handle_request(&mut self, request: Value) -> Option<Value> {
-        let method = request["method"].as_str()?;
-        let id = &request["id"];
+    fn handle_call(&mut self, method: &str, params: &Value) -> Result<Value, ResponseError> {
+        if self.shutdown_requested {
+            return Err(ResponseError::new(
+                ErrorCode::InvalidRequest,
+                format!("{} received after shutdown", method),
+            ));
+        }
 
         match method {
             "initialize" => {
-                Some(json!({
-                    "jsonrpc": "2.0",
-                    "id": id,
-                    "result": {
-                        "capabilities": {
-                            "definitionProvider": true,
-                            "declarationProvider": true,
-                            "textDocumentSync": {
-                                "openClose": true,
-                                "change": 1
-                            }
+                self.workspace.encoding = negotiate_position_encoding(&params["capabilities"]);
+                if let Some(version) = params["initializationOptions"]["haproxyVersion"].as_str() {
+                    match version.parse() {
+                        Ok(version) => self.workspace.haproxy_version = Some(version),
+                        Err(err) => eprintln!("HAProxy LSP: ignoring haproxyVersion: {}", err),
+                    }
+                }
+                Ok(json!({
+                    "capabilities": {
+                        "positionEncoding": self.workspace.encoding.name(),
+                        "definitionProvider": true,
+                        "declarationProvider": true,
+                        "referencesProvider": true,
+                        "completionProvider": {},
+                        "hoverProvider": true,
+                        "codeActionProvider": { "codeActionKinds": ["quickfix"] },
+                        "textDocumentSync": {
+                            "openClose": true,
+                            "change": 2
                         }
                     }
                 }))
             }
+            "shutdown" => {
+                self.shutdown_requested = true;
+                self.workspace = Workspace::default();
+                // Nothing changes any more, so stop the diagnostics worker
+                self.diagnostics = None;
+                Ok(Value::Null)
+            }
+            _ => self.workspace.handle_query(method, params),
+        }
+    }
+
+    /// Takes a request off the main thread if it belongs on a worker, returning
+    /// its id, method, params and the workspace snapshot to answer it from.
+    fn background_job(&self, request: &Value) -> Option<(Value, String, Value, Workspace)> {
+        let id = request.get("id")?;
+        let method = request["method"].as_str()?;
+        let background = BACKGROUND_METHODS.contains(&method) || (cfg!(test) && method.starts_with("test/"));
+        if self.shutdown_requested || !background {
+            return None;
+        }
+        Some((id.clone(), method.to_string(), request["params"].clone(), self.workspace.clone()))
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> Result<(), ResponseError> {
+        // After shutdown only exit is accepted
+        if self.shutdown_requested && method != "exit" {
+            return Ok(());
+        }
+
+        // The document a notification changes, and what other files read from it before
+        let changed = match method {
+            "textDocument/didOpen" | "textDocument/didChange" | "textDocument/didClose" => {
+                let uri = str_param(params, "/textDocument/uri")?;
+                Some((uri, self.workspace.facts.get(uri).cloned()))
+            }
+            _ => None,
+        };
+        if let (Some(_), Some(queue)) = (&changed, &self.diagnostics) {
+            // A snapshot the worker hasn't taken yet would make the edit copy the document
+            queue.snapshot.lock().unwrap().take();
+        }
+
+        match method {
+            "initialized" => {}
+            "exit" => {
+                self.exit_code = Some(if self.shutdown_requested { 0 } else { 1 });
+            }
             "textDocument/didOpen" => {
-                let params = &request["params"];
-                let uri = params["textDocument"]["uri"].as_str()?;
-                let content = params["textDocument"]["text"].as_str()?;
-                
-                if let Err(_) = self.parse_document(uri, content) {
-                    eprintln!("Failed to parse document: {}", uri);
+                let uri = str_param(params, "/textDocument/uri")?;
+                let content = str_param(params, "/textDocument/text")?;
+                let version = params["textDocument"]["version"].as_i64().unwrap_or(0);
+
+                if let Err(err) = self.parse_document(uri, content.to_string(), version) {
+                    eprintln!("Failed to parse document {}: {}", uri, err);
                 }
-                
-                None // No response needed for notifications
             }
             "textDocument/didChange" => {
-                let params = &request["params"];
-                let uri = params["textDocument"]["uri"].as_str()?;
-                let changes = params["contentChanges"].as_array()?;
-                
-                if let Some(change) = changes.first() {
-                    if let Some(content) = change["text"].as_str() {
-                        if let Err(_) = self.parse_document(uri, content) {
-                            eprintln!("Failed to parse document: {}", uri);
-                        }
+                let uri = str_param(params, "/textDocument/uri")?;
+                let version = params["textDocument"]["version"].as_i64().unwrap_or(0);
+                let changes = params["contentChanges"]
+                    .as_array()
+                    .ok_or_else(|| ResponseError::invalid_params("/contentChanges"))?;
+
+                if let Some(document) = self.workspace.documents.get(uri) {
+                    if version < document.version {
+                        eprintln!("Ignoring stale change to {} (version {} < {})", uri, version, document.version);
+                        return Ok(());
                     }
                 }
-                
-                None // No response needed for notifications
-            }
-            "textDocument/definition" => {
-                let params = &request["params"];
-                let uri = params["textDocument"]["uri"].as_str()?;
-                let position = Position {
-                    line: params["position"]["line"].as_u64()? as u32,
-                    character: params["position"]["character"].as_u64()? as u32,
-                };
 
-                // For this basic implementation, we'll need to re-read the file content
-                // In production, we'd cache the content from didOpen/didChange events
-                if let Ok(content) = std::fs::read_to_string(uri.strip_prefix("file://").unwrap_or(uri)) {
-                    if let Some(symbol) = self.find_definition(uri, &position, &content) {
-                        Some(json!({
-                            "jsonrpc": "2.0",
-                            "id": id,
-                            "result": {
-                                "uri": symbol.uri,
-                                "range": {
-                                    "start": {
-                                        "line": symbol.range.start.line,
-                                        "character": symbol.range.start.character
-                                    },
-                                    "end": {
-                                        "line": symbol.range.end.line,
-                                        "character": symbol.range.end.character
-                                    }
-                                }
-                            }
-                        }))
-                    } else {
-                        Some(json!({
-                            "jsonrpc": "2.0",
-                            "id": id,
-                            "result": null
-                        }))
+                // Changes apply in order; one without a range replaces the whole text
+                for change in changes {
+                    let content = match change["text"].as_str() {
+                        Some(content) => content,
+                        None => continue,
+                    };
+                    let result = match parse_range(&change["range"]) {
+                        Some(range) => {
+                            let range = Range {
+                                start: self.workspace.position_from_client(uri, range.start),
+                                end: self.workspace.position_from_client(uri, range.end),
+                            };
+                            self.edit_document(uri, &range, content, version)
+                        }
+                        None => self.parse_document(uri, content.to_string(), version),
+                    };
+                    if let Err(err) = result {
+                        eprintln!("Failed to parse document {}: {}", uri, err);
                     }
-                } else {
-                    Some(json!({
-                        "jsonrpc": "2.0",
-                        "id": id,
-                        "result": null
-                    }))
                 }
             }
-            "textDocument/declaration" => {
-                let params = &request["params"];
-                let uri = params["textDocument"]["uri"].as_str()?;
-                let position = Position {
-                    line: params["position"]["line"].as_u64()? as u32,
-                    character: params["position"]["character"].as_u64()? as u32,
-                };
+            // The store already holds every edit, so saving changes nothing
+            "textDocument/didSave" => {}
+            // Lets the tests check that a panicking handler is contained
+            #[cfg(test)]
+            "test/panic" => panic!("test/panic"),
+            "textDocument/didClose" => {
+                let uri = str_param(params, "/textDocument/uri")?;
+                self.close_document(uri);
+            }
+            // Unknown notifications, `$/` ones included, may be ignored
+            _ => return Ok(()),
+        }
 
-                // For this basic implementation, we'll need to re-read the file content
-                if let Ok(content) = std::fs::read_to_string(uri.strip_prefix("file://").unwrap_or(uri)) {
-                    if let Some(references) = self.find_declaration(uri, &position, &content) {
-                        // Return array of locations for multiple references
-                        let locations: Vec<Value> = references.into_iter().map(|reference| {
-                            json!({
-                                "uri": reference.uri,
-                                "range": {
-                                    "start": {
-                                        "line": reference.range.start.line,
-                                        "character": reference.range.start.character
-                                    },
-                                    "end": {
-                                        "line": reference.range.end.line,
-                                        "character": reference.range.end.character
-                                    }
-                                }
-                            })
-                        }).collect();
-
-                        Some(json!({
-                            "jsonrpc": "2.0",
-                            "id": id,
-                            "result": locations
-                        }))
-                    } else {
-                        Some(json!({
-                            "jsonrpc": "2.0",
-                            "id": id,
-                            "result": []
-                        }))
-                    }
-                } else {
-                    Some(json!({
-                        "jsonrpc": "2.0",
-                        "id": id,
-                        "result": []
-                    }))
+        if let Some((uri, facts_before)) = changed {
+            self.schedule_diagnostics(uri, facts_before);
+        }
+        Ok(())
+    }
+
+    /// Queues `uri` for the diagnostics worker, with every other open document when
+    /// the change altered what their diagnostics read from it.
+    fn schedule_diagnostics(&self, uri: &str, facts_before: Option<Arc<CrossFileFacts>>) {
+        let queue = match &self.diagnostics {
+            Some(queue) => queue,
+            None => return,
+        };
+        let mut uris = vec![uri.to_string()];
+        if self.workspace.facts.get(uri) != facts_before.as_ref() {
+            uris.extend(self.workspace.load_order.iter().filter(|open| *open != uri).cloned());
+        }
+        *queue.snapshot.lock().unwrap() = Some(self.workspace.clone());
+        // The worker is gone only when the server is exiting
+        let _ = queue.jobs.send(uris);
+    }
+}
+
+/// How the main loop hands work to the diagnostics worker: the documents whose
+/// diagnostics may have changed, and the latest snapshot to check them in.
+struct DiagnosticsQueue {
+    jobs: Sender<Vec<String>>,
+    /// Taken back before each edit, so only a snapshot the worker is checking
+    /// shares the documents being edited.
+    snapshot: Arc<Mutex<Option<Workspace>>>,
+}
+
+/// How long edits must pause before the diagnostics worker checks the documents.
+const DIAGNOSTICS_DELAY: Duration = Duration::from_millis(100);
+
+/// Publishes `textDocument/publishDiagnostics` for queued documents whose diagnostics
+/// changed, checking them in the latest snapshot once edits pause for
+/// [`DIAGNOSTICS_DELAY`]. Closed documents are cleared. Runs until the queue closes,
+/// finishing the jobs queued by then.
+fn publish_diagnostics(jobs: Receiver<Vec<String>>, snapshot: Arc<Mutex<Option<Workspace>>>, outgoing: Sender<Value>) {
+    // Diagnostics last published per open URI
+    let mut published: HashMap<String, Vec<Value>> = HashMap::new();
+    let mut pending: HashSet<String> = HashSet::new();
+
+    while let Ok(uris) = jobs.recv() {
+        pending.extend(uris);
+        while let Ok(uris) = jobs.recv_timeout(DIAGNOSTICS_DELAY) {
+            pending.extend(uris);
+        }
+        // Missing only while an edit is applied, which queues a job with the next one
+        let workspace = match snapshot.lock().unwrap().take() {
+            Some(workspace) => workspace,
+            None => continue,
+        };
+
+        let mut uris: Vec<String> = pending.drain().collect();
+        uris.sort();
+        for uri in uris {
+            let diagnostics: Vec<Value> = workspace
+                .diagnostics(&uri)
+                .iter()
+                .map(|diagnostic| diagnostic_json(&workspace, &uri, diagnostic))
+                .collect();
+            let changed = match workspace.documents.contains_key(&uri) {
+                true => published.insert(uri.clone(), diagnostics.clone()).as_ref() != Some(&diagnostics),
+                false => published.remove(&uri).is_some(),
+            };
+            if !changed {
+                continue;
+            }
+
+            let notification = json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/publishDiagnostics",
+                "params": {
+                    "uri": uri,
+                    "diagnostics": diagnostics
                 }
+            });
+            // The writer is gone only when the server is exiting
+            if outgoing.send(notification).is_err() {
+                return;
             }
-            _ => None,
         }
     }
 }
 
+/// Where a keyword may appear and which releases accept it, e.g. for hover.
+fn keyword_summary(keyword: &Keyword) -> String {
+    let sections: Vec<&str> = keyword.sections.kinds().map(SectionKind::keyword).collect();
+    let mut summary = format!("Allowed in: {}\n\nSince HAProxy {}", sections.join(", "), keyword.since);
+    if let Some(removed) = keyword.removed {
+        summary.push_str(&format!(", removed in {}", removed));
+    }
+    if let Some(replacement) = keyword.replaced_by {
+        summary.push_str(&format!("\n\nUse `{}` instead", replacement));
+    }
+    summary
+}
+
+/// JSON-RPC and LSP error codes the server reports.
+#[derive(Debug, Clone, Copy)]
+enum ErrorCode {
+    ParseError = -32700,
+    InvalidRequest = -32600,
+    MethodNotFound = -32601,
+    InvalidParams = -32602,
+    InternalError = -32603,
+    RequestCancelled = -32800,
+}
+
+#[derive(Debug)]
+struct ResponseError {
+    code: ErrorCode,
+    message: String,
+}
+
+impl ResponseError {
+    fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ResponseError { code, message: message.into() }
+    }
+
+    fn invalid_params(pointer: &str) -> Self {
+        ResponseError::new(ErrorCode::InvalidParams, format!("missing or invalid parameter {}", pointer))
+    }
+}
+
+fn error_response(id: &Value, error: ResponseError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": error.code as i32,
+            "message": error.message
+        }
+    })
+}
+
+fn str_param<'a>(params: &'a Value, pointer: &str) -> Result<&'a str, ResponseError> {
+    params
+        .pointer(pointer)
+        .and_then(Value::as_str)
+        .ok_or_else(|| ResponseError::invalid_params(pointer))
+}
+
+fn position_param(params: &Value, pointer: &str) -> Result<Position, ResponseError> {
+    let position = params.pointer(pointer).ok_or_else(|| ResponseError::invalid_params(pointer))?;
+    let field = |name: &str| {
+        position[name]
+            .as_u64()
+            .map(|value| value as u32)
+            .ok_or_else(|| ResponseError::invalid_params(&format!("{}/{}", pointer, name)))
+    };
+    Ok(Position { line: field("line")?, character: field("character")? })
+}
+
+fn parse_range(value: &Value) -> Option<Range> {
+    let position = |value: &Value| {
+        Some(Position {
+            line: value["line"].as_u64()? as u32,
+            character: value["character"].as_u64()? as u32,
+        })
+    };
+    Some(Range { start: position(&value["start"])?, end: position(&value["end"])? })
+}
+
+fn range_json(range: &Range) -> Value {
+    json!({
+        "start": {
+            "line": range.start.line,
+            "character": range.start.character
+        },
+        "end": {
+            "line": range.end.line,
+            "character": range.end.character
+        }
+    })
+}
+
+fn location_json(uri: &str, range: &Range) -> Value {
+    json!({
+        "uri": uri,
+        "range": range_json(range)
+    })
+}
+
+/// An LSP diagnostic on `uri`, with its ranges in the client's position encoding.
+fn diagnostic_json(workspace: &Workspace, uri: &str, diagnostic: &Diagnostic) -> Value {
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+        Severity::Information => 3,
+        Severity::Hint => 4,
+    };
+    let mut value = json!({
+        "range": range_json(&workspace.range_to_client(uri, &diagnostic.range)),
+        "severity": severity,
+        "code": diagnostic.code,
+        "source": "haproxy",
+        "message": diagnostic.message
+    });
+    if !diagnostic.tags.is_empty() {
+        let tags: Vec<u8> = diagnostic
+            .tags
+            .iter()
+            .map(|tag| match tag {
+                DiagnosticTag::Unnecessary => 1,
+                DiagnosticTag::Deprecated => 2,
+            })
+            .collect();
+        value["tags"] = json!(tags);
+    }
+    if !diagnostic.related.is_empty() {
+        let related: Vec<Value> = diagnostic
+            .related
+            .iter()
+            .map(|related| {
+                json!({
+                    "location": location_json(&related.uri, &workspace.range_to_client(&related.uri, &related.range)),
+                    "message": related.message
+                })
+            })
+            .collect();
+        value["relatedInformation"] = json!(related);
+    }
+    value
+}
+
+/// A job for the worker pool.
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Most worker threads the server starts, however many cores there are.
+const MAX_WORKERS: usize = 4;
+
+/// A fixed set of worker threads taking jobs from one queue in order.
+struct WorkerPool {
+    jobs: Option<Sender<Job>>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    fn new(size: usize) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        let workers = (0..size)
+            .map(|_| {
+                let queue = queue.clone();
+                thread::spawn(move || loop {
+                    // The lock is only held while taking a job, not while running it
+                    let job = match queue.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => return,
+                    };
+                    job();
+                })
+            })
+            .collect();
+        WorkerPool { jobs: Some(jobs), workers }
+    }
+
+    fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        if let Some(jobs) = &self.jobs {
+            // Workers only stop once the pool is dropped
+            let _ = jobs.send(Box::new(job));
+        }
+    }
+}
+
+impl Drop for WorkerPool {
+    /// Lets the workers finish the queued jobs, then waits for them.
+    fn drop(&mut self) {
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Runs background requests on the worker pool and answers cancelled ones with
+/// `RequestCancelled` instead of their result.
+struct Dispatcher {
+    outgoing: Sender<Value>,
+    /// Cancellation flags of the requests queued or running, keyed by serialized id.
+    in_flight: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+    pool: WorkerPool,
+}
+
+impl Dispatcher {
+    fn new(outgoing: Sender<Value>) -> Self {
+        let workers = thread::available_parallelism().map_or(1, |cores| cores.get().min(MAX_WORKERS));
+        Dispatcher { outgoing, in_flight: Arc::new(Mutex::new(HashMap::new())), pool: WorkerPool::new(workers) }
+    }
+
+    fn spawn(&self, id: Value, method: String, params: Value, workspace: Workspace) {
+        let key = id.to_string();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.in_flight.lock().unwrap().insert(key.clone(), cancelled.clone());
+
+        let outgoing = self.outgoing.clone();
+        let in_flight = self.in_flight.clone();
+        self.pool.execute(move || {
+            // A request cancelled while queued is not worth starting
+            let query = || workspace.handle_query(&method, &params);
+            let result = match cancelled.load(Ordering::SeqCst) {
+                true => Ok(Value::Null),
+                false => std::panic::catch_unwind(std::panic::AssertUnwindSafe(query))
+                    .unwrap_or_else(|_| Err(ResponseError::new(ErrorCode::InternalError, format!("{} failed", method)))),
+            };
+            in_flight.lock().unwrap().remove(&key);
+
+            let response = match result {
+                _ if cancelled.load(Ordering::SeqCst) => error_response(
+                    &id,
+                    ResponseError::new(ErrorCode::RequestCancelled, format!("{} was cancelled", method)),
+                ),
+                Ok(result) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "result": result
+                }),
+                Err(err) => error_response(&id, err),
+            };
+            // The writer is gone only when the server is exiting
+            let _ = outgoing.send(response);
+        });
+    }
+
+    /// Flags a queued or running request as cancelled; requests already answered are left alone.
+    fn cancel(&self, id: &Value) {
+        if let Some(cancelled) = self.in_flight.lock().unwrap().get(&id.to_string()) {
+            cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Writes one message with its `Content-Length` header.
+fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    lsp_framing::write_message(writer, &serde_json::to_vec(message)?)
+}
+
+const USAGE: &str = "\
+Usage: haproxy-lsp [--stdio | --port <n> | --pipe <path>] [--listen]
+       haproxy-lsp check [--format <format>] [--haproxy-version <v>] -f <path>...
+
+Language server for HAProxy configuration files.
+
+Options:
+  --stdio          Talk LSP over stdin/stdout (default)
+  --port <n>       Connect to the client listening on 127.0.0.1:<n>
+  --pipe <path>    Connect to the client listening on the Unix socket <path>
+  --listen         Listen on the port or socket instead and serve the first
+                   client that connects
+  --version        Print the version and exit
+  --help           Print this help and exit
+
+Check options:
+  -f <path>        Config file, or directory of *.cfg files, to check (repeatable)
+  --format <f>     Output as human (default), json or sarif
+  --haproxy-version <v>
+                   Also report keywords HAProxy <v> (e.g. 2.8) doesn't accept;
+                   a `# haproxy-lsp: version=<v>` comment in a file overrides it
+
+check exits with 1 if any error was found.";
+
+/// Where the server reads requests from and writes responses to.
+enum Transport {
+    Stdio,
+    Tcp(u16),
+    Pipe(String),
+}
+
+enum Command {
+    /// Serve over `transport`, listening for the client rather than connecting to it if `listen`.
+    Serve { transport: Transport, listen: bool },
+    Check(check::CheckOptions),
+    Version,
+    Help,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut transport = Transport::Stdio;
+    let mut listen = false;
+    let mut args = args.into_iter().peekable();
+    if args.peek().map(String::as_str) == Some("check") {
+        args.next();
+        return check::parse_args(args);
+    }
+
+    while let Some(arg) = args.next() {
+        // Accept both `--port 9257` and `--port=9257`
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg, None),
+        };
+        let mut value = |name: &str| {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} requires a value", name))
+        };
+
+        transport = match flag.as_str() {
+            "--stdio" => Transport::Stdio,
+            "--port" => {
+                let port = value("--port")?;
+                Transport::Tcp(port.parse().map_err(|_| format!("invalid port: {}", port))?)
+            }
+            "--pipe" => Transport::Pipe(value("--pipe")?),
+            "--listen" => {
+                listen = true;
+                continue;
+            }
+            "--version" | "-V" => return Ok(Command::Version),
+            "--help" | "-h" => return Ok(Command::Help),
+            other => return Err(format!("unknown argument: {}", other)),
+        };
+    }
+
+    if listen && matches!(transport, Transport::Stdio) {
+        return Err("--listen requires --port or --pipe".to_string());
+    }
+    Ok(Command::Serve { transport, listen })
+}
+
+/// The connection to an LSP client on 127.0.0.1:`port`. Clients usually listen and
+/// wait for the server they started to connect; with `listen` the roles swap.
+fn tcp_stream(port: u16, listen: bool) -> io::Result<std::net::TcpStream> {
+    if !listen {
+        return std::net::TcpStream::connect(("127.0.0.1", port));
+    }
+    let listener = std::net::TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("HAProxy LSP: listening on {}", listener.local_addr()?);
+    let (stream, peer) = listener.accept()?;
+    eprintln!("HAProxy LSP: client connected from {}", peer);
+    Ok(stream)
+}
+
+/// Like [`tcp_stream`] over the Unix socket at `path`.
+#[cfg(unix)]
+fn unix_stream(path: &str, listen: bool) -> io::Result<std::os::unix::net::UnixStream> {
+    if !listen {
+        return std::os::unix::net::UnixStream::connect(path);
+    }
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    eprintln!("HAProxy LSP: listening on {}", path);
+    let accepted = listener.accept();
+    // The socket file is only needed until the client is connected
+    let _ = std::fs::remove_file(path);
+    Ok(accepted?.0)
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let (transport, listen) = match parse_args(std::env::args().skip(1)) {
+        Ok(Command::Serve { transport, listen }) => (transport, listen),
+        Ok(Command::Check(options)) => std::process::exit(check::run(&options).unwrap_or_else(|err| {
+            eprintln!("haproxy-lsp: {}", err);
+            2
+        })),
+        Ok(Command::Version) => {
+            println!("haproxy-lsp {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        Err(err) => {
+            eprintln!("haproxy-lsp: {}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let exit_code = match transport {
+        Transport::Stdio => serve(io::stdin().lock(), io::stdout())?,
+        Transport::Tcp(port) => {
+            let stream = tcp_stream(port, listen)?;
+            serve(io::BufReader::new(stream.try_clone()?), stream)?
+        }
+        #[cfg(unix)]
+        Transport::Pipe(path) => {
+            let stream = unix_stream(&path, listen)?;
+            serve(io::BufReader::new(stream.try_clone()?), stream)?
+        }
+        #[cfg(not(unix))]
+        Transport::Pipe(_) => return Err("--pipe is only supported on Unix".into()),
+    };
+
+    std::process::exit(exit_code);
+}
+
+/// Runs the server over one connection until `exit` or end of input, returning
+/// the process exit code.
+fn serve(input: impl BufRead, output: impl Write + Send + 'static) -> Result<i32, Box<dyn std::error::Error>> {
     let mut lsp = HaproxyLsp::new()?;
-    let mut stdin = io::stdin();
-    let mut stdout = io::stdout();
+    if let Some(notice) = &lsp.grammar_notice {
+        eprintln!("HAProxy LSP: {}", notice);
+    }
+    let mut reader = MessageReader::new(input);
 
+    // Responses from the main loop and from workers share one writer thread
+    let (outgoing, responses) = mpsc::channel::<Value>();
+    let writer = thread::spawn(move || -> io::Result<()> {
+        let mut output = output;
+        for response in responses {
+            write_message(&mut output, &response)?;
+        }
+        Ok(())
+    });
+    let dispatcher = Dispatcher::new(outgoing.clone());
+    let (diagnostics_jobs, jobs) = mpsc::channel();
+    let snapshot = Arc::new(Mutex::new(None));
+    lsp.diagnostics = Some(DiagnosticsQueue { jobs: diagnostics_jobs, snapshot: snapshot.clone() });
+    let diagnostics_outgoing = outgoing.clone();
+    let diagnostics = thread::spawn(move || publish_diagnostics(jobs, snapshot, diagnostics_outgoing));
     loop {
-        // Read LSP message with Content-Length header
-        let mut header_line = String::new();
-        let bytes_read = stdin.read_line(&mut header_line)?;
-        
-        if bytes_read == 0 {
-            break; // EOF
-        }
-        
-        // Parse Content-Length header
-        let content_length = if header_line.starts_with("Content-Length: ") {
-            header_line
-                .strip_prefix("Content-Length: ")
-                .and_then(|s| s.trim().parse::<usize>().ok())
-                .unwrap_or(0)
-        } else {
-            continue;
+        let buffer = match reader.read_message() {
+            Ok(Some(buffer)) => buffer,
+            Ok(None) => break, // EOF
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                eprintln!("HAProxy LSP: skipping message: {}", err);
+                continue;
+            }
+            Err(err) => return Err(err.into()),
         };
-        
-        if content_length == 0 {
-            continue;
+
+        // Parse JSON-RPC request; unparseable messages get an error with a null id
+        let response = match serde_json::from_slice::<Value>(&buffer) {
+            Ok(request) if request["method"] == "$/cancelRequest" => {
+                dispatcher.cancel(&request["params"]["id"]);
+                None
+            }
+            Ok(request) => match lsp.background_job(&request) {
+                Some((id, method, params, workspace)) => {
+                    dispatcher.spawn(id, method, params, workspace);
+                    None
+                }
+                None => lsp.handle_request(request),
+            },
+            Err(err) => Some(error_response(
+                &Value::Null,
+                ResponseError::new(ErrorCode::ParseError, format!("invalid JSON: {}", err)),
+            )),
+        };
+
+        if let Some(response) = response {
+            outgoing.send(response)?;
+        }
+
+        if lsp.exit_code.is_some() {
+            break;
+        }
+    }
+
+    // Let the workers and then the writer drain what is queued before exiting
+    drop(dispatcher);
+    lsp.diagnostics = None;
+    diagnostics.join().map_err(|_| "diagnostics thread panicked")?;
+    drop(outgoing);
+    writer.join().map_err(|_| "writer thread panicked")??;
+
+    Ok(lsp.exit_code.unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(text: &str, start: usize) -> Token {
+        let position = |character: usize| Position { line: 0, character: character as u32 };
+        Token {
+            text: text.to_string(),
+            span: start..start + text.len(),
+            range: Range { start: position(start), end: position(start + text.len()) },
+        }
+    }
+
+    /// What the server wrote, shared with the test since `serve` writes from its own thread.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        /// The messages written so far, in order.
+        fn messages(&self) -> Vec<Value> {
+            let bytes = self.0.lock().unwrap().clone();
+            let mut reader = MessageReader::new(&bytes[..]);
+            let mut messages = Vec::new();
+            while let Some(message) = reader.read_message().unwrap() {
+                messages.push(serde_json::from_slice(&message).unwrap());
+            }
+            messages
+        }
+    }
+
+    fn request(id: i64, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    fn notification(method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "method": method, "params": params })
+    }
+
+    /// Serves `messages` until they run out and returns the exit code and what the server sent.
+    fn session(messages: &[Value]) -> (i32, Vec<Value>) {
+        let mut input = Vec::new();
+        for message in messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let output = Output::default();
+        let code = serve(io::Cursor::new(input), output.clone()).unwrap();
+        (code, output.messages())
+    }
+
+    /// A client talking to `serve` on another thread through a pipe, so that each
+    /// message can wait for the server's answer to the previous ones.
+    struct Client {
+        input: Option<io::PipeWriter>,
+        output: Output,
+        server: thread::JoinHandle<i32>,
+    }
+
+    impl Client {
+        fn start() -> Self {
+            let (reader, input) = io::pipe().unwrap();
+            let output = Output::default();
+            let server_output = output.clone();
+            let server = thread::spawn(move || serve(io::BufReader::new(reader), server_output).unwrap());
+            Client { input: Some(input), output, server }
+        }
+
+        fn send(&mut self, message: Value) {
+            write_message(self.input.as_mut().unwrap(), &message).unwrap();
+        }
+
+        /// Waits for the response to request `id`.
+        fn response(&self, id: i64) -> Value {
+            for _ in 0..500 {
+                if let Some(response) = self.output.messages().into_iter().find(|message| message["id"] == id) {
+                    return response;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            panic!("no response to {}", id);
+        }
+
+        /// Closes the input and returns the exit code and everything the server sent.
+        fn finish(mut self) -> (i32, Vec<Value>) {
+            self.input.take();
+            (self.server.join().unwrap(), self.output.messages())
+        }
+    }
+
+    fn cancel(id: i64) -> Value {
+        notification("$/cancelRequest", json!({ "id": id }))
+    }
+
+    /// The response to request `id`, which must be the only one.
+    fn response(messages: &[Value], id: i64) -> &Value {
+        let responses: Vec<&Value> = messages.iter().filter(|message| message["id"] == id).collect();
+        assert_eq!(responses.len(), 1, "{:?}", messages);
+        responses[0]
+    }
+
+    #[test]
+    fn requests_after_shutdown_are_invalid_and_exit_reports_whether_it_came() {
+        let position = json!({
+            "textDocument": { "uri": "file:///haproxy.cfg" },
+            "position": { "line": 0, "character": 0 }
+        });
+        let (code, messages) = session(&[
+            request(1, "initialize", json!({ "capabilities": {} })),
+            notification("initialized", json!({})),
+            request(2, "shutdown", Value::Null),
+            request(3, "textDocument/hover", position.clone()),
+            request(4, "textDocument/definition", position),
+            notification("exit", Value::Null),
+            request(5, "shutdown", Value::Null),
+        ]);
+        assert_eq!(code, 0);
+        assert_eq!(response(&messages, 2)["result"], Value::Null);
+        assert_eq!(response(&messages, 3)["error"]["code"], -32600);
+        assert_eq!(response(&messages, 4)["error"]["code"], -32600);
+        // Nothing is read after exit
+        assert!(messages.iter().all(|message| message["id"] != 5), "{:?}", messages);
+
+        let (code, messages) = session(&[request(1, "initialize", json!({})), notification("exit", Value::Null)]);
+        assert_eq!(code, 1);
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[test]
+    fn responses_from_the_client_are_not_answered() {
+        let (_, messages) = session(&[
+            json!({ "jsonrpc": "2.0", "id": 1, "result": null }),
+            json!({ "jsonrpc": "2.0", "id": 2, "error": { "code": -32601, "message": "unknown" } }),
+            json!({ "jsonrpc": "2.0", "id": 3 }),
+        ]);
+        assert_eq!(messages.len(), 1, "{:?}", messages);
+        assert_eq!(response(&messages, 3)["error"]["code"], -32600);
+    }
+
+    #[test]
+    fn failing_notifications_leave_the_server_running() {
+        let (code, messages) = session(&[
+            request(1, "initialize", json!({})),
+            notification("textDocument/didChange", json!({ "textDocument": { "uri": 7 } })),
+            notification("test/panic", Value::Null),
+            request(2, "shutdown", Value::Null),
+            notification("exit", Value::Null),
+        ]);
+        assert_eq!(code, 0);
+        assert_eq!(response(&messages, 2)["result"], Value::Null);
+    }
+
+    #[test]
+    fn cancelled_requests_are_answered_with_request_cancelled() {
+        let mut client = Client::start();
+        client.send(request(1, "initialize", json!({})));
+        client.send(request(2, "test/sleep", json!(300)));
+        client.send(cancel(2));
+        assert_eq!(client.response(2)["error"]["code"], -32800);
+
+        // Cancelling a request that was already answered, or never made, changes nothing
+        client.send(request(3, "test/sleep", json!(0)));
+        assert_eq!(client.response(3)["result"], 0);
+        client.send(cancel(3));
+        client.send(cancel(42));
+        client.send(request(4, "shutdown", Value::Null));
+        client.response(4);
+
+        let (_, messages) = client.finish();
+        for id in 1..=4 {
+            response(&messages, id);
+        }
+        assert_eq!(messages.len(), 4, "{:?}", messages);
+    }
+
+    #[test]
+    fn slow_background_requests_do_not_hold_up_the_others() {
+        let mut messages = vec![request(1, "initialize", json!({}))];
+        messages.extend((2..6).map(|id| request(id, "test/sleep", json!(100))));
+        messages.push(request(6, "shutdown", Value::Null));
+        let (_, messages) = session(&messages);
+
+        let ids: Vec<&Value> = messages.iter().map(|message| &message["id"]).collect();
+        assert_eq!(ids.len(), 6, "{:?}", messages);
+        // Answered on the main loop while the workers still sleep
+        assert!(ids[..2].contains(&&json!(6)), "{:?}", ids);
+        for id in 2..6 {
+            assert_eq!(response(&messages, id)["result"], 100);
+        }
+    }
+
+    #[test]
+    fn a_panicking_request_is_answered_with_an_internal_error() {
+        let mut client = Client::start();
+        client.send(request(1, "initialize", json!({})));
+        client.send(request(2, "test/panic", Value::Null));
+        assert_eq!(client.response(2)["error"]["code"], -32603);
+        // The worker that ran it still takes requests
+        client.send(request(3, "test/sleep", json!(0)));
+        assert_eq!(client.response(3)["result"], 0);
+        client.send(request(4, "shutdown", Value::Null));
+        client.send(notification("exit", Value::Null));
+        assert_eq!(client.finish().0, 0);
+    }
+
+    #[test]
+    fn a_version_pragma_overrides_the_setting_and_follows_edits() {
+        let uri = "file:///haproxy.cfg";
+        let mut lsp = HaproxyLsp::new().unwrap();
+        lsp.workspace.haproxy_version = Some(Version::new(2, 8));
+        lsp.parse_document(uri, "frontend a\n  bind quic4@:443\n".to_string(), 1).unwrap();
+        let unsupported = |lsp: &HaproxyLsp| -> Vec<u32> {
+            let diagnostics = lsp.workspace.diagnostics(uri);
+            let unsupported = diagnostics.iter().filter(|found| found.code == "unsupported-keyword");
+            unsupported.map(|found| found.range.start.line).collect()
+        };
+        assert!(unsupported(&lsp).is_empty());
+
+        let at = |line, character| Range { start: Position { line, character }, end: Position { line, character } };
+        lsp.edit_document(uri, &at(0, 0), "# haproxy-lsp: version=2.4\n", 2).unwrap();
+        assert_eq!(unsupported(&lsp), [2]);
+        // An edit away from the comment keeps the pragma
+        lsp.edit_document(uri, &at(2, 0), "  bind :80\n", 3).unwrap();
+        assert_eq!(unsupported(&lsp), [3]);
+        let version = Range { start: Position { line: 0, character: 24 }, end: Position { line: 0, character: 27 } };
+        lsp.edit_document(uri, &version, "2.6", 4).unwrap();
+        assert!(unsupported(&lsp).is_empty());
+    }
+
+    #[test]
+    fn keywords_removed_before_1_8_are_flagged_and_fixed() {
+        let text = "defaults\n  redispatch\n  option independant-streams\nbackend b\n  appsession JSESSIONID len 52\n";
+        let uri = "file:///haproxy.cfg";
+        let mut lsp = HaproxyLsp::new().unwrap();
+        lsp.parse_document(uri, text.to_string(), 1).unwrap();
+
+        for version in [None, Some(Version::new(2, 8))] {
+            lsp.workspace.haproxy_version = version;
+            let workspace = &lsp.workspace;
+            let diagnostics: Vec<Value> = workspace
+                .diagnostics(uri)
+                .iter()
+                .filter(|diagnostic| diagnostic.code != "unused-backend")
+                .map(|diagnostic| diagnostic_json(workspace, uri, diagnostic))
+                .collect();
+            let lines: Vec<&Value> = diagnostics.iter().map(|found| &found["range"]["start"]["line"]).collect();
+            assert_eq!(lines, [1, 2, 4]);
+
+            for (line, fix) in [(1, Some("option redispatch")), (2, Some("option independent-streams")), (4, None)] {
+                let range = Range { start: Position { line, character: 0 }, end: Position { line, character: 0 } };
+                let actions = workspace.code_actions(uri, &range, &diagnostics);
+                // Besides the one rewriting every removed keyword of the file
+                let fixes: Vec<&Value> = actions
+                    .iter()
+                    .filter(|action| action["isPreferred"] == true)
+                    .map(|action| &action["edit"]["changes"][uri][0]["newText"])
+                    .collect();
+                match fix {
+                    Some(fix) => {
+                        assert_eq!(fixes, [fix]);
+                        assert_eq!(actions[0]["diagnostics"][0]["range"]["start"]["line"], line);
+                    }
+                    // `stick on` needs a table, so there is no mechanical fix
+                    None => assert!(actions.is_empty(), "{:?}", actions),
+                }
+            }
         }
-        
-        // Read empty line separator
-        let mut empty_line = String::new();
-        stdin.read_line(&mut empty_line)?;
-        
-        // Read the JSON content
-        let mut buffer = vec![0; content_length];
-        stdin.read_exact(&mut buffer)?;
-        let content = String::from_utf8(buffer)?;
-        
-        // Parse JSON-RPC request
-        if let Ok(request) = serde_json::from_str::<Value>(&content) {
-            if let Some(response) = lsp.handle_request(request) {
-                let response_str = serde_json::to_string(&response)?;
-                let response_len = response_str.len();
-                
-                // Write LSP response with headers
-                write!(stdout, "Content-Length: {}\r\n\r\n{}", response_len, response_str)?;
-                stdout.flush()?;
-            }
-        }
-    }
-
-    Ok(())
-}
\ No newline at end of file
+    }
+
+    #[test]
+    fn port_and_pipe_connect_to_the_client_unless_told_to_listen() {
+        let args = |args: &[&str]| parse_args(args.iter().map(|arg| arg.to_string()));
+        let connect = args(&["--port", "9257"]);
+        assert!(matches!(connect, Ok(Command::Serve { transport: Transport::Tcp(9257), listen: false })));
+        assert!(matches!(args(&["--listen", "--pipe=/tmp/s"]), Ok(Command::Serve { listen: true, .. })));
+        assert!(args(&["--listen"]).is_err());
+
+        let client = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = client.local_addr().unwrap().port();
+        let server = tcp_stream(port, false).unwrap();
+        let (accepted, _) = client.accept().unwrap();
+        assert_eq!(accepted.peer_addr().unwrap(), server.local_addr().unwrap());
+
+        #[cfg(unix)]
+        {
+            let path = std::env::temp_dir().join(format!("haproxy-lsp-test-{}.sock", std::process::id()));
+            let client = std::os::unix::net::UnixListener::bind(&path).unwrap();
+            unix_stream(path.to_str().unwrap(), false).unwrap();
+            client.accept().unwrap();
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn grammar_tokens_are_used_only_when_they_cover_the_tokenizer() {
+        let backend = TokenRole::Reference(SymbolKind::Backend, ReferenceContext::UseBackend);
+        let acl = TokenRole::Reference(SymbolKind::Acl, ReferenceContext::AclCondition);
+        // use_backend app if is_api
+        let tokenized = vec![(token("app", 12), backend.clone()), (token("is_api", 19), acl)];
+
+        let partial = vec![(token("app", 12), backend.clone())];
+        assert!(covering(partial, &tokenized).is_none());
+        assert!(covering(Vec::new(), &tokenized).is_none());
+        assert!(covering(Vec::new(), &[]).is_none());
+
+        let complete = vec![(token("app", 12), backend), (token("is_api", 19), TokenRole::Definition(SymbolKind::Acl))];
+        let tokens = covering(complete, &tokenized).unwrap();
+        assert!(matches!(tokens[1].1, TokenRole::Definition(SymbolKind::Acl)));
+    }
+
+    #[test]
+    fn navigation_follows_sections_an_edit_moved() {
+        let text = "defaults\n  acl d always_true\n\nfrontend www\n  acl x always_true\n  use_backend app if x d\n\n\
+                    backend app\n  server s1 10.0.0.1:80\n";
+        let uri = "file:///haproxy.cfg";
+        let mut lsp = HaproxyLsp::new().unwrap();
+        lsp.parse_document(uri, text.to_string(), 1).unwrap();
+        let at = |line, character| Range { start: Position { line, character }, end: Position { line, character } };
+        lsp.edit_document(uri, &at(0, 0), "# top\n\n", 2).unwrap();
+        lsp.edit_document(uri, &at(7, 0), "  acl y always_true\n", 3).unwrap();
+
+        let document = lsp.workspace.documents.get(uri).unwrap();
+        assert_eq!(document.lines, LineIndex::new(&document.text));
+        let starts = |locations: Vec<(String, Range)>| -> Vec<(u32, u32)> {
+            locations.iter().map(|(_, range)| (range.start.line, range.start.character)).collect()
+        };
+        let definition = lsp.workspace.symbol_at(uri, &Position { line: 8, character: 15 }).unwrap();
+        assert_eq!((definition.range.start.line, definition.range.start.character), (10, 8));
+        let references = lsp.workspace.find_references(uri, &Position { line: 3, character: 6 }, true).unwrap();
+        assert_eq!(starts(references), vec![(3, 6), (8, 23)]);
+        let references = lsp.workspace.find_references(uri, &Position { line: 6, character: 6 }, true).unwrap();
+        assert_eq!(starts(references), vec![(6, 6), (8, 21)]);
+    }
+
+    #[cfg(haproxy_grammar)]
+    #[test]
+    fn grammar_classifies_every_name_for_navigation_and_diagnostics() {
+        let text = "frontend www\n  bind :80\n  acl is_api path_beg /api\n  use_backend api if is_api\n  \
+                    http-request deny if !is_api missing\n  default_backend app\n\nbackend app\n  server s1 10.0.0.1:80\n";
+        let uri = "file:///haproxy.cfg";
+        let mut lsp = HaproxyLsp::new().unwrap();
+        assert!(lsp.grammar_notice.is_none(), "{:?}", lsp.grammar_notice);
+        lsp.parse_document(uri, text.to_string(), 1).unwrap();
+
+        let document = lsp.workspace.documents.get(uri).unwrap();
+        assert!(document.tree.is_some());
+        for directive in document.config.directives() {
+            let classified = document.symbol_tokens(directive);
+            for (token, _) in directive.symbol_tokens() {
+                assert!(classified.iter().any(|(found, _)| found.span == token.span), "{} unclassified", token.text);
+            }
+        }
+
+        let diagnostics = lsp.workspace.diagnostics(uri);
+        let codes: Vec<(&str, u32)> =
+            diagnostics.iter().map(|diagnostic| (diagnostic.code, diagnostic.range.start.line)).collect();
+        assert!(codes.contains(&("undefined-backend", 3)), "{:?}", codes);
+        assert!(codes.contains(&("undefined-acl", 4)), "{:?}", codes);
+        // The same names navigation resolves
+        let position = Position { line: 5, character: 20 };
+        assert_eq!(lsp.workspace.symbol_at(uri, &position).map(|symbol| symbol.name), Some("app".to_string()));
+    }
+}